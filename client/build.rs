@@ -1,3 +1,16 @@
+use std::path::Path;
+
+// Sprite sheets are encoded into the compact animation format at build time so that only the
+// compressed frames end up in flash.
+fn encode_sprites() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=media");
+    sprite::encode_sheets(Path::new("media"), Path::new(&out_dir)).unwrap();
+}
+
 fn main() {
+    encode_sprites();
+    // Baked into the firmware as the release public key updates are checked with
+    println!("cargo:rerun-if-env-changed=MB_OTA_PUBLIC_KEY");
     embuild::espidf::sysenv::output();
 }
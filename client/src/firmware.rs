@@ -0,0 +1,112 @@
+//! Firmware updates over the air.
+//!
+//! An `OTA` directive names an image on the server. It is downloaded straight into the OTA slot
+//! the board isn't running from and checked with an [`ota::Verifier`] on the way, signature
+//! included when the board was built with `MB_OTA_PUBLIC_KEY` set. Only an image that passes is
+//! made the one to boot. A board built without the key has only the digest of the directive to
+//! go by, so it takes updates from directives sealed for its session alone, see [`allowed`].
+//!
+//! The bootloader is built with rollback enabled (see `sdkconfig.defaults`), so a new image boots
+//! once on probation. It is kept by [`confirm`] after it registered with the server. Should it
+//! restart before that, or not manage to register for [`PROBATION`], the previous image boots
+//! again and registers in its place, which is how the server learns the update failed.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use esp_idf_svc::ota::{EspOta, SlotState};
+
+use embedded_svc::http::client::Client;
+use embedded_svc::io::{Read, Write};
+
+/// How long a new image gets to register before the previous one is booted again.
+pub const PROBATION: Duration = Duration::from_secs(120);
+
+const CHUNK_SIZE: usize = 4096;
+
+const RELEASE_KEY: Option<&str> = option_env!("MB_OTA_PUBLIC_KEY");
+
+/// Whether an update may be installed, `sealed` telling whether its directive came sealed.
+pub fn allowed(sealed: bool) -> bool {
+    sealed || RELEASE_KEY.is_some()
+}
+
+/// Download and check the image of `update`, then make it the one to boot. The caller restarts.
+pub fn install(update: &ota::Update) -> Result<()> {
+    let public_key = match RELEASE_KEY {
+        Some(hex) => Some(ota::parse_hex(hex).ok_or_else(|| anyhow!("MB_OTA_PUBLIC_KEY isn't a public key"))?),
+        None => None,
+    };
+
+    let connection = EspHttpConnection::new(&Configuration {
+        buffer_size: Some(CHUNK_SIZE),
+        timeout: Some(Duration::from_secs(10)),
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+    let mut response = client.get(&update.url)?.submit()?;
+    if response.status() != 200 {
+        return Err(anyhow!("server answered {}", response.status()));
+    }
+
+    let mut ota = EspOta::new()?;
+    let mut slot = ota.initiate_update()?;
+    let mut verifier = ota::Verifier::new(update);
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+
+    // Anything going wrong leaves the slot half written, abort so it isn't booted
+    let written = (|| -> Result<()> {
+        loop {
+            let n = response.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            verifier.update(&buffer[..n])?;
+            slot.write_all(&buffer[..n])?;
+        }
+        Ok(())
+    })();
+
+    match written.and_then(|()| Ok(verifier.finish(public_key.as_ref())?)) {
+        Ok(()) => {
+            slot.complete()?;
+            log::info!("Update to {} written, restarting", update.version);
+            Ok(())
+        },
+        Err(e) => {
+            slot.abort()?;
+            Err(e)
+        }
+    }
+}
+
+/// Keep the running image if it is on probation, once it registered with the server.
+pub fn confirm() {
+    let Ok(mut ota) = EspOta::new() else { return };
+
+    if ota.get_running_slot().is_ok_and(|slot| matches!(slot.state, SlotState::Unverified)) {
+        match ota.mark_running_slot_valid() {
+            Ok(()) => log::info!("Firmware {} confirmed", env!("CARGO_PKG_VERSION")),
+            Err(e) => log::error!("Unable to confirm firmware: {}", e),
+        }
+    }
+}
+
+/// Boot the previous image if the running one is on probation and hasn't registered since `booted`
+/// for longer than [`PROBATION`].
+pub fn check_probation(booted: Instant) {
+    if booted.elapsed() < PROBATION {
+        return;
+    }
+    let Ok(mut ota) = EspOta::new() else { return };
+
+    if ota.get_running_slot().is_ok_and(|slot| matches!(slot.state, SlotState::Unverified)) {
+        log::error!("Firmware {} never registered, rolling back", env!("CARGO_PKG_VERSION"));
+        // Only comes back if it failed
+        let e = ota.mark_running_slot_invalid_and_reboot();
+        log::error!("Rollback failed: {}", e);
+    }
+}
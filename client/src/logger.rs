@@ -0,0 +1,58 @@
+//! Logging to the serial console, and to the server.
+//!
+//! Everything goes through the ESP-IDF logger to the console as before. Lines at info and up are
+//! also queued, the newest [`QUEUE_LENGTH`] of them, and forwarded to the server in answer to the
+//! next directive.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use esp_idf_svc::log::EspLogger;
+use log::{Level, Log, Metadata, Record};
+use telemetry::LogLine;
+
+/// Lines kept for the server, older ones are dropped when it isn't reachable for a while.
+pub const QUEUE_LENGTH: usize = 32;
+
+static CONSOLE: EspLogger = EspLogger::new();
+static LOGGER: ForwardingLogger = ForwardingLogger { queue: Mutex::new(VecDeque::new()) };
+
+struct ForwardingLogger {
+    queue: Mutex<VecDeque<LogLine>>,
+}
+
+impl Log for ForwardingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        CONSOLE.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        CONSOLE.log(record);
+
+        if record.level() > Level::Info || !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = LogLine { level: record.level().as_str().to_lowercase(), message: record.args().to_string() };
+
+        let mut queue = self.queue.lock().unwrap();
+        if queue.len() == QUEUE_LENGTH {
+            queue.pop_front();
+        }
+        queue.push_back(line);
+    }
+
+    fn flush(&self) {
+        CONSOLE.flush();
+    }
+}
+
+/// Install the logger, in place of `EspLogger::initialize_default`.
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap();
+    CONSOLE.initialize();
+}
+
+/// The lines queued since the last call, oldest first.
+pub fn take() -> Vec<LogLine> {
+    LOGGER.queue.lock().unwrap().drain(..).collect()
+}
@@ -6,40 +6,39 @@ use std::net::TcpStream;
 use std::net::TcpListener;
 use std::io::Write;
 use std::io::Read;
+use std::io::{BufRead, BufReader};
 use std::io::ErrorKind;
 use std::time::Instant;
-use std::str::FromStr;
 
-use tinybmp::Bmp;
+use render::{Overlay, Screen};
+use sprite::Frame;
+use sprite::codec::SpriteDecoder;
 
-use embedded_graphics::image::GetPixel;
+use registration::Session;
+use telemetry::Telemetry;
 
-use esp_idf_svc::ipv4::SocketAddrV4;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use config;
 use embedded_graphics::{
-    mono_font::{ascii::FONT_6X10, MonoTextStyleBuilder},
     pixelcolor::BinaryColor,
     prelude::*,
-    text::{Baseline, Text},
 };
-use embedded_graphics::primitives::Rectangle;
 //use embedded_graphics::image::Image;
 
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
+    nvs::EspDefaultNvsPartition,
     hal::{
         i2c::{I2cConfig, I2cDriver},
         prelude::*,
+        reset::ResetReason,
     },
+    sys::{esp, esp_get_free_heap_size, esp_timer_get_time, esp_wifi_sta_get_ap_info, wifi_ap_record_t},
 };
 
 
-use embedded_graphics::{
-    primitives::{Sector, PrimitiveStyle, PrimitiveStyleBuilder},
-};
-
 use ssd1306::mode::BufferedGraphicsMode;
 
 //use std::sync::atomic::AtomicBool;
@@ -47,11 +46,15 @@ use std::sync::atomic::Ordering;
 use std::sync::Mutex;
 use std::sync::Arc;
 
-use embedded_graphics::mono_font::ascii::FONT_10X20;
-use embedded_graphics::mono_font::ascii::FONT_6X13;
 
 use ssd1306::{prelude::*, I2CDisplayInterface, Ssd1306};
-use wifi::wifi;
+use wifi::{Backoff, Status as WifiStatus};
+
+use provisioning::Credentials;
+
+mod firmware;
+mod logger;
+mod setup;
 
 
 use atomic_enum::atomic_enum;
@@ -66,116 +69,126 @@ enum Animation {
 }
 
 
-struct Sprite<'a> {
-    bmp: Bmp<'a,BinaryColor>,
-    rows: usize,
-    cols: usize,
-    width: usize,
-    height: usize,
-    frame_count: usize,
-}
-
-impl<'a> Sprite<'a> {
-
-    fn new(bmp_data: &'a[u8], width: usize, height: usize, cols: usize, rows: usize, frame_count: usize) -> Self {
-        Sprite {
-            bmp: Bmp::<BinaryColor>::from_slice(bmp_data).unwrap(),
-            rows: rows,
-            cols: cols,
-            width: width,
-            height: height,
-            frame_count: frame_count,
-        }
+// `None` for animations this board wasn't built with
+fn parse_animation(animation_name: &str) -> Option<Animation> {
+    match animation_name {
+        "CartoonEyes" => Some(Animation::CartoonEyes),
+        "Unicorn" => Some(Animation::Unicorn),
+        "Heart" => Some(Animation::Heart),
+        _ => None,
     }
-
 }
 
-fn update_animation<DI, SIZE, MODE>(_display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, MODE>>>>, animation_name: &str, animation: &Arc<AtomicAnimation>) {
-    let animation_update = match animation_name {
-        "CartoonEyes" => Animation::CartoonEyes,
-        "Unicorn" => Animation::Unicorn,
-        "Heart" => Animation::Heart,
-        _ => panic!("Unkknown Animation"),
+fn update_animation<DI, SIZE, MODE>(_display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, MODE>>>>, animation_name: &str, animation: &Arc<AtomicAnimation>, overlay: &Arc<Mutex<Overlay>>) {
+    let Some(animation_update) = parse_animation(animation_name) else {
+        log::warn!("Ignoring unknown animation {}", animation_name);
+        return;
     };
 
+    *overlay.lock().unwrap() = Overlay::default();
     animation.store(animation_update, Ordering::Relaxed);
 }
 
-fn update_message<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>>>, message: &str, animation: &Arc<AtomicAnimation>) {
+// Write a frame straight into the display RAM. This bypasses the buffered graphics framebuffer,
+// which is fine since every text update clears and fully redraws it.
+fn blit_frame<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &mut Ssd1306<DI, SIZE, MODE>, frame: &Frame) {
+    display.set_draw_area((0, 0), (frame.width() as u8, frame.height() as u8)).unwrap();
+    display.draw(frame.as_bytes()).unwrap();
+}
 
-    animation.store(Animation::Off, Ordering::Relaxed);
+fn update_scene<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>>>, background: Option<&str>, layers: Overlay, animation: &Arc<AtomicAnimation>, overlay: &Arc<Mutex<Overlay>>) {
+
+    let background = match background {
+        Some(name) => match parse_animation(name) {
+            Some(background) => background,
+            None => {
+                log::warn!("Ignoring scene with unknown animation {}", name);
+                return;
+            }
+        },
+        None => Animation::Off,
+    };
 
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_6X13)
-        .text_color(BinaryColor::On)
-        .build();
+    *overlay.lock().unwrap() = layers;
+    animation.store(background, Ordering::Relaxed);
 
-    {
+    // Without a background the animation thread is idle, so the overlay is drawn here
+    if background == Animation::Off {
         let mut active_display = display.lock().unwrap();
         active_display.clear(BinaryColor::Off).unwrap();
-        Text::with_baseline(message, Point::new(0, 0), text_style, Baseline::Top)
-            .draw(&mut **active_display)
-            .unwrap();
+        render::draw_overlay(&mut **active_display, &overlay.lock().unwrap()).unwrap();
         active_display.flush().unwrap();
     }
-
 }
 
-fn update_timer<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>>>, timer: &str, animation: &Arc<AtomicAnimation>) {
+fn update_message<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>>>, message: &str, animation: &Arc<AtomicAnimation>) {
 
     animation.store(Animation::Off, Ordering::Relaxed);
 
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_10X20)
-        .text_color(BinaryColor::On)
-        .build();
-
-    let mut parts = timer.split('/');
-
-    match (parts.next(), parts.next()) {
-        (Some(current),Some(total)) => {
-            let ratio = 360.0 * f32::from_str(current).unwrap() / f32::from_str(total).unwrap();
-
-            let mut active_display = display.lock().unwrap();
-
-            active_display.clear(BinaryColor::Off).unwrap();
-
-            Text::with_baseline(current, Point::new(0, 0), text_style, Baseline::Top)
-                .draw(&mut **active_display)
-                .unwrap();
+    {
+        let mut active_display = display.lock().unwrap();
+        render::draw_message(&mut **active_display, message).unwrap();
+        active_display.flush().unwrap();
+    }
 
-            Text::with_baseline(total, Point::new(0, 22), text_style, Baseline::Top)
-                .draw(&mut **active_display)
-                .unwrap();
+}
 
-            // Circle Outline
-            Sector::new(Point::new(65, 1), 60, -90.0.deg(), 360.0.deg())
-                .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
-                .draw(&mut **active_display).unwrap();
+fn update_timer<DI: WriteOnlyDataCommand, SIZE: ssd1306::prelude::DisplaySize, MODE>(display: &Arc<Mutex<Box<Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>>>>, current: u64, total: u64, animation: &Arc<AtomicAnimation>) {
 
-            if ratio > 0.0 {
-                // Circle Fill
-                Sector::new(Point::new(65, 1), 60, -90.0.deg(), ratio.deg())
-                    .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
-                    .draw(&mut **active_display).unwrap();
+    animation.store(Animation::Off, Ordering::Relaxed);
 
-            } else {
+    let mut active_display = display.lock().unwrap();
+    render::draw_timer(&mut **active_display, current, total).unwrap();
+    active_display.flush().unwrap();
+}
 
-                Text::with_baseline("Done!", Point::new(0, 44), text_style, Baseline::Top)
-                    .draw(&mut **active_display)
-                    .unwrap();
-            }
+// What the board can tell about itself, for the server's portal
+fn telemetry(display_status: &str) -> Telemetry {
+    let mut ap_info = wifi_ap_record_t::default();
+    let rssi = esp!(unsafe { esp_wifi_sta_get_ap_info(&mut ap_info) }).ok().map(|_| ap_info.rssi as i32);
+
+    Telemetry {
+        rssi,
+        uptime: Some(unsafe { esp_timer_get_time() } as u64 / 1_000_000),
+        reset_reason: Some(format!("{:?}", ResetReason::get()).to_lowercase()),
+        free_heap: Some(unsafe { esp_get_free_heap_size() }),
+        firmware: Some(env!("CARGO_PKG_VERSION").to_string()),
+        display: Some(display_status.to_string()),
+    }
+}
 
-            active_display.flush().unwrap();
-        }
+// Send the registration request and answer the server's challenge if it has one. Answering
+// starts a session the server seals its directives, and we our replies, with. Once accepted the
+// first report follows.
+fn register(stream: &mut TcpStream, mac_address: &str, key: Option<&[u8]>, telemetry: &Telemetry) -> Result<Option<Session>> {
+    stream.write_all(registration::request(mac_address, None).as_bytes())?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let mut session = None;
+    if let Some(nonce) = registration::parse_challenge(&line) {
+        let key = key.ok_or_else(|| anyhow!("the server asked for a key but none was provisioned"))?;
+        stream.write_all(registration::response(key, &nonce, mac_address).as_bytes())?;
+        session = Some(Session::new(key, &nonce, mac_address));
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
 
-        (_,_) => panic!("Unrecognized Timer Format"),
+    match line.trim() {
+        registration::ACCEPTED => {
+            stream.write_all(registration::reply(session.as_mut(), &telemetry.line()).as_bytes())?;
+            Ok(session)
+        },
+        answer => Err(anyhow!("refused by the server: {:?}", answer)),
     }
 }
 
 fn main() -> Result<()> {
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    logger::init();
 
     let peripherals = Peripherals::take().unwrap();
     let sysloop = EspSystemEventLoop::take()?;
@@ -191,60 +204,120 @@ fn main() -> Result<()> {
 
     // Place the buffered_graphics display on the stack to prevent Stack overflow
     let mut display = Box::new(display.into_buffered_graphics_mode());
-    display.init().unwrap();
+    // Reported to the server with the rest of the telemetry
+    let display_status = match display.init() {
+        Ok(()) => "ok",
+        Err(e) => {
+            log::error!("Display init failed: {:?}", e);
+            "error"
+        }
+    };
 
     let display = Arc::new(Mutex::new(display));
 
-    let text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_6X10)
-        .text_color(BinaryColor::On)
-        .build();
+    let mut settings = setup::Settings::open(EspDefaultNvsPartition::take()?)?;
 
-    {
-        let mut display = display.lock().unwrap();
-        Text::with_baseline("Connecting...", Point::zero(), text_style, Baseline::Top)
-            .draw(&mut **display)
-            .unwrap();
-        display.flush().unwrap();
-    }
+    // Settings from NVS win, a board that was never provisioned tries the network it was built with
+    let credentials = match settings.credentials() {
+        Ok(Some(credentials)) => Some(credentials),
+        Ok(None) => Credentials::new(config::WIFI_SSID, config::WIFI_PSK, None).ok(),
+        Err(e) => {
+            log::warn!("Stored settings unusable: {}", e);
+            None
+        }
+    };
 
-    // Connect to the Wi-Fi network
-    let wifi = wifi(
-        config::WIFI_SSID,
-        config::WIFI_PSK,
-        peripherals.modem,
-        sysloop,
-    )?;
+    let credentials = match credentials {
+        Some(credentials) if !settings.setup_requested()? => credentials,
+        _ => {
+            settings.request_setup(false)?;
+            {
+                let mut display = display.lock().unwrap();
+                render::draw_message(&mut **display, &format!("Setup: join WiFi\n{}\nhttp://192.168.71.1", setup::ACCESS_POINT)).unwrap();
+                display.flush().unwrap();
+            }
+            setup::run(&mut settings, peripherals.modem, sysloop)?;
+            unreachable!();
+        }
+    };
 
+    let animation = Arc::new(AtomicAnimation::new(Animation::Off));
+    let overlay = Arc::new(Mutex::new(Overlay::default()));
 
-    {
-        let mut display = display.lock().unwrap();
-        Text::with_baseline("Connected!", Point::new(0, 16), text_style, Baseline::Top)
-            .draw(&mut **display)
-            .unwrap();
-        display.flush().unwrap();
+    // The provisioned network first, then the one the board was built with as a fallback
+    let mut networks = vec![wifi::Network { ssid: credentials.ssid.clone(), psk: credentials.psk.clone() }];
+    if let Ok(built_in) = Credentials::new(config::WIFI_SSID, config::WIFI_PSK, None) {
+        if built_in.ssid != credentials.ssid {
+            networks.push(wifi::Network { ssid: built_in.ssid, psk: built_in.psk });
+        }
     }
 
-    let server_addr = SocketAddrV4::new(config::SERVER_IP, config::BROADCAST_PORT);
+    // Connect to the Wi-Fi network
+    let wifi = match wifi::connect(networks, 4, Backoff::default(), peripherals.modem, sysloop, {
+        let display = display.clone();
+        let animation = animation.clone();
+        move |status| {
+            let text = match status {
+                WifiStatus::Connecting(ssid) => format!("Connecting to\n{}", ssid),
+                WifiStatus::Connected(ssid) => format!("Connected to\n{}", ssid),
+                WifiStatus::Disconnected => "WiFi lost".to_string(),
+                WifiStatus::Retrying { attempt, delay } => format!("WiFi retry {}\nin {}s", attempt, delay.as_secs()),
+            };
+            log::info!("{}", text.replace('\n', " "));
+
+            // Connection trouble takes the screen from the animation
+            if !matches!(status, WifiStatus::Connected(_)) {
+                animation.store(Animation::Off, Ordering::Relaxed);
+            }
+            let mut display = display.lock().unwrap();
+            render::draw_message(&mut **display, &text).unwrap();
+            display.flush().unwrap();
+        }
+    }) {
+        Ok(wifi) => wifi,
+        Err(e) => {
+            // The modem belongs to the failed connection now, setup mode gets it after a restart
+            log::error!("Unable to join {}: {}", credentials.ssid, e);
+            settings.request_setup(true)?;
+            esp_idf_svc::hal::reset::restart();
+        }
+    };
+
+    // Look for the server on the local network, falling back to the provisioned or built in address
+    let fallback_addr = credentials.server.unwrap_or(SocketAddr::V4(SocketAddrV4::new(config::SERVER_IP, config::BROADCAST_PORT)));
+    let server_addr = match discovery::discover(SocketAddr::from((Ipv4Addr::BROADCAST, discovery::DISCOVERY_PORT)), Duration::from_millis(500), 3) {
+        Ok(Some(addr)) => {
+            log::info!("Discovered MicroBroadcaster at {}", addr);
+            addr
+        },
+        Ok(None) => {
+            log::warn!("No MicroBroadcaster answered, using {}", fallback_addr);
+            fallback_addr
+        },
+        Err(e) => {
+            log::error!("Discovery failed: {}", e);
+            fallback_addr
+        }
+    };
 
     let mac_chunks = wifi.get_mac(esp_idf_svc::wifi::WifiDeviceId::Sta).unwrap();
-    let registration_request = format!("REGISTER {:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}", mac_chunks[0], mac_chunks[1], mac_chunks[2], mac_chunks[3], mac_chunks[4], mac_chunks[5]).into_bytes();
+    let mac_address = format!("{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}", mac_chunks[0], mac_chunks[1], mac_chunks[2], mac_chunks[3], mac_chunks[4], mac_chunks[5]);
 
     let mut current_cmd = "".to_string();
-
-    let animation = Arc::new(AtomicAnimation::new(Animation::Off));
+    let mut last_report = Instant::now();
+    let booted = Instant::now();
 
     // animation thread
     std::thread::spawn({
         let animation = animation.clone();
+        let animation_overlay = overlay.clone();
         let animation_display = display.clone();
 
         move || {
 
-            let cartoon_eyes = Arc::new(Sprite::new(include_bytes!("../media/eyes.bmp"), 128, 64, 10, 4, 40));
-            let heart        = Arc::new(Sprite::new(include_bytes!("../media/heart.bmp"), 128, 64, 4, 7, 28));
-            let unicorn      = Arc::new(Sprite::new(include_bytes!("../media/unicorn.bmp"), 128, 64, 4, 7, 28));
-            let off          = Arc::new(Sprite::new(include_bytes!("../media/heart.bmp"), 128, 64, 4, 7, 28));
+            let cartoon_eyes: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/eyes.mbs"));
+            let heart: &[u8]        = include_bytes!(concat!(env!("OUT_DIR"), "/heart.mbs"));
+            let unicorn: &[u8]      = include_bytes!(concat!(env!("OUT_DIR"), "/unicorn.mbs"));
 
             loop {
 
@@ -255,35 +328,31 @@ fn main() -> Result<()> {
                 } else {
 
                     let sprite = match current_animation {
-                        Animation::Unicorn     => unicorn.clone(),
-                        Animation::Heart       => heart.clone(),
-                        Animation::CartoonEyes => cartoon_eyes.clone(),
-                        Animation::Off         => off.clone(),
+                        Animation::Unicorn     => unicorn,
+                        Animation::Heart       => heart,
+                        Animation::CartoonEyes => cartoon_eyes,
+                        Animation::Off         => unreachable!(),
                     };
 
-                    for frame_index in 0..sprite.frame_count {
+                    // Frames are decoded one at a time as they are played, so only a single
+                    // frame of the animation is ever held in memory.
+                    let mut decoder = SpriteDecoder::new(sprite).unwrap();
+
+                    while let Some(frame) = decoder.next_frame().unwrap() {
                         // Check if the current animation is still valid
                         if current_animation == animation.load(Ordering::Relaxed) {
 
-                            let row = frame_index / sprite.cols;
-                            let col = frame_index % sprite.cols;
-
-                            let frame_origin = Point::new((col*sprite.width) as i32, (row*sprite.height) as i32);
-                            let frame_bounds = Rectangle::new(frame_origin, Size::new(sprite.width as u32, sprite.height as u32 ));
+                            let overlay = animation_overlay.lock().unwrap().clone();
 
                             let mut display = animation_display.lock().unwrap();
 
-                            display.clear(BinaryColor::Off).unwrap();
-
-                            for point in frame_bounds.points() {
-                                let pixel = sprite.bmp.pixel(point).unwrap();
-
-                                if pixel == BinaryColor::On {
-                                    let draw_point = point - frame_origin;
-                                    display.set_pixel(draw_point.x.try_into().unwrap(), draw_point.y.try_into().unwrap(), true);
-                                }
+                            if overlay.is_empty() {
+                                blit_frame(&mut **display, frame);
+                            } else {
+                                let mut composed = frame.clone();
+                                render::draw_overlay(&mut composed, &overlay).unwrap();
+                                blit_frame(&mut **display, &composed);
                             }
-                            display.flush().unwrap();
                         } else {
                             break;
                         }
@@ -297,23 +366,35 @@ fn main() -> Result<()> {
 
     // Main loop
     loop {
-        println!("Searching for MicroBroadcaster at {:?}", server_addr);
+        log::info!("Searching for MicroBroadcaster at {:?}", server_addr);
 
-        match TcpStream::connect(server_addr)
+        let mut session = match TcpStream::connect(server_addr)
         {
             Ok(mut stream) => {
-                println!("Sending Registration request.");
-                match stream.write_all(&registration_request) {
-                    Ok(_n) => println!("Registration Successfull"),
-                    Err(e) => println!("Registration failed {}", e)
-                };
+                log::debug!("Sending Registration request.");
+                match register(&mut stream, &mac_address, credentials.key.as_deref(), &telemetry(display_status)) {
+                    Ok(session) => {
+                        log::info!("Registration Successfull");
+                        firmware::confirm();
+                        session
+                    },
+                    Err(e) => {
+                        log::warn!("Registration failed {}", e);
+                        firmware::check_probation(booted);
+                        std::thread::sleep(Duration::from_millis(1000));
+                        continue;
+                    }
+                }
             },
             Err(error) => {
-                println!("Invalid Response: {}", error);
+                log::warn!("Invalid Response: {}", error);
+                firmware::check_probation(booted);
+                // Whatever was on screen may have been replaced meanwhile, show the next directive again
+                current_cmd.clear();
                 std::thread::sleep(Duration::from_millis(1000));
                 continue;
             }
-        }
+        };
 
         // now read until an error occurs then break out of the loop
         let listener = TcpListener::bind(format!("0.0.0.0:{}",config::BROADCAST_PORT)).unwrap();
@@ -331,20 +412,62 @@ fn main() -> Result<()> {
                     match socket.read_to_string(&mut cmd) {
                         Ok(_n) => (),
                         Err(e) => {
-                            println!("Read Error: {}", e);
+                            log::warn!("Read Error: {}", e);
                             break;
                         }
                     }
 
-                    println!("Received Directive: {}", &cmd);
+                    // Once registered with a key only sealed directives are trusted
+                    if let Some(session) = &mut session {
+                        match session.open(&cmd) {
+                            Some(directive) => cmd = directive,
+                            None => {
+                                log::warn!("Dropping directive that isn't sealed for this session");
+                                continue;
+                            }
+                        }
+                    }
+
+                    if last_report.elapsed() >= telemetry::INTERVAL {
+                        let report = registration::reply(session.as_mut(), &telemetry(display_status).line());
+                        let _ = socket.write_all(report.as_bytes());
+                        last_report = Instant::now();
+                    }
+                    // What was logged since the last directive goes along
+                    for line in logger::take() {
+                        let _ = socket.write_all(registration::reply(session.as_mut(), &line.line()).as_bytes());
+                    }
+
+                    log::debug!("Received Directive: {}", &cmd);
+
+                    // Updates take over the board until they are done, on failure registering
+                    // again tells the server which firmware is still running
+                    if let Some(update) = ota::Update::parse(&cmd) {
+                        drop(socket);
+                        if update.version == env!("CARGO_PKG_VERSION") {
+                            log::info!("Already running {}, update skipped", update.version);
+                            continue;
+                        }
+                        if !firmware::allowed(session.is_some()) {
+                            log::warn!("Ignoring update to {}, unsealed and no release key to check it", update.version);
+                            continue;
+                        }
+                        update_message::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, &format!("Updating to\n{}", update.version), &animation);
+                        match firmware::install(&update) {
+                            Ok(()) => esp_idf_svc::hal::reset::restart(),
+                            Err(e) => log::error!("Update to {} failed: {}", update.version, e),
+                        }
+                        current_cmd.clear();
+                        break;
+                    }
                     if cmd != current_cmd
                     {
                         if cmd != "PING" {
-                            match cmd.split_once(' ') {
-                                Some(("ANIMATE", a)) => update_animation(&display, a, &animation),
-                                Some(("MESSAGE", m)) => update_message::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, m, &animation),
-                                Some(("TIMER", t))   => update_timer::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, t, &animation),
-                                Some((_,_)) => panic!("Unrecognized command"),
+                            match Screen::parse(&cmd) {
+                                Some(Screen::Animation(a)) => update_animation(&display, &a, &animation, &overlay),
+                                Some(Screen::Message(m)) => update_message::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, &m, &animation),
+                                Some(Screen::Timer { current, total }) => update_timer::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, current, total, &animation),
+                                Some(Screen::Scene { animation: background, overlay: layers }) => update_scene::<I2CInterface<I2cDriver<'_>>, ssd1306::prelude::DisplaySize128x64, BufferedGraphicsMode<ssd1306::prelude::DisplaySize128x64>>(&display, background.as_deref(), layers, &animation, &overlay),
                                 None => panic!("Unrecognized command"),
                             };
                         }
@@ -355,14 +478,14 @@ fn main() -> Result<()> {
                 Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
                     // No incoming connection, check if we should timeout
                     if start_time.elapsed() >= timeout {
-                        println!("Timeout reached, breaking out of accept loop");
+                        log::warn!("Timeout reached, breaking out of accept loop");
                         break;
                     }
                     // Optional: sleep for a short duration to avoid busy-waiting
                     std::thread::sleep(Duration::from_millis(100));
                 }
                 Err(e) => {
-                    println!("Error: {}", e);
+                    log::error!("Error: {}", e);
                     break;
                 }
             }
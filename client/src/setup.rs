@@ -0,0 +1,174 @@
+// Network settings kept in NVS, and the setup mode that collects them.
+//
+// In setup mode the board opens an access point with a form at http://192.168.71.1/ and also
+// accepts a `PROVISION ...` line on the serial console. Whichever arrives first is stored and the
+// board restarts to use it.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Result;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::{
+    eventloop::EspSystemEventLoop,
+    hal::{modem::Modem, peripheral},
+    http::{server::{Configuration, EspHttpServer}, Method},
+    nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault},
+};
+
+use provisioning::Credentials;
+
+pub const ACCESS_POINT: &str = "MicroBroadcast-Setup";
+
+const NAMESPACE: &str = "mb";
+
+// Give up waiting after a while and try the stored network again, it may just have been down
+const SETUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+const MAX_FORM_LEN: usize = 512;
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width"><title>MicroBroadcast Setup</title></head>
+<body><h1>MicroBroadcast Setup</h1>
+<form method="post" action="/">
+<p><label>WiFi name<br><input name="ssid" maxlength="32" required></label></p>
+<p><label>WiFi password<br><input name="psk" type="password" maxlength="64"></label></p>
+<p><label>Server address (optional)<br><input name="server" placeholder="192.168.4.209"></label></p>
+<p><label>Registration key (optional)<br><input name="key" maxlength="128"></label></p>
+<p><button type="submit">Save</button></p>
+</form></body></html>"#;
+
+pub struct Settings {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Settings {
+
+    pub fn open(partition: EspDefaultNvsPartition) -> Result<Self> {
+        Ok(Settings { nvs: EspNvs::new(partition, NAMESPACE, true)? })
+    }
+
+    /// Stored credentials, `None` when the board was never provisioned.
+    pub fn credentials(&self) -> Result<Option<Credentials>> {
+        let mut buf = [0u8; 160];
+
+        let ssid = match self.nvs.get_str("ssid", &mut buf)? {
+            Some(ssid) => ssid.to_string(),
+            None => return Ok(None),
+        };
+        let psk = self.nvs.get_str("psk", &mut buf)?.unwrap_or("").to_string();
+        let server = self.nvs.get_str("server", &mut buf)?.unwrap_or("").to_string();
+        let key = self.nvs.get_str("key", &mut buf)?.unwrap_or("").to_string();
+
+        let mut credentials = Credentials::new(&ssid, &psk, provisioning::parse_server(&server, config::BROADCAST_PORT)?)?;
+        credentials.key = provisioning::parse_key(&key)?;
+        Ok(Some(credentials))
+    }
+
+    pub fn store(&mut self, credentials: &Credentials) -> Result<()> {
+        self.nvs.set_str("ssid", &credentials.ssid)?;
+        self.nvs.set_str("psk", &credentials.psk)?;
+        match credentials.server {
+            Some(server) => self.nvs.set_str("server", &server.to_string())?,
+            None => { self.nvs.remove("server")?; }
+        }
+        match &credentials.key {
+            Some(key) => self.nvs.set_str("key", &registration::to_hex(key))?,
+            None => { self.nvs.remove("key")?; }
+        }
+        Ok(())
+    }
+
+    /// Whether the last boot asked for setup mode, after failing to connect.
+    pub fn setup_requested(&self) -> Result<bool> {
+        Ok(self.nvs.get_u8("setup")? == Some(1))
+    }
+
+    pub fn request_setup(&mut self, requested: bool) -> Result<()> {
+        self.nvs.set_u8("setup", requested as u8)?;
+        Ok(())
+    }
+}
+
+/// Wait for new settings over the access point or serial, store them and restart.
+pub fn run(
+    settings: &mut Settings,
+    modem: impl peripheral::Peripheral<P = Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+) -> Result<()> {
+    let _wifi = wifi::access_point(ACCESS_POINT, modem, sysloop)?;
+
+    let (sender, receiver) = mpsc::channel();
+
+    let mut server = EspHttpServer::new(&Configuration::default())?;
+
+    server.fn_handler("/", Method::Get, |req| -> Result<()> {
+        req.into_ok_response()?.write_all(PAGE.as_bytes())?;
+        Ok(())
+    })?;
+
+    server.fn_handler("/", Method::Post, {
+        let sender = sender.clone();
+        move |mut req| -> Result<()> {
+            let mut body = Vec::new();
+            let mut buf = [0u8; 128];
+            loop {
+                let n = req.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                // Nothing of a form cut short is stored
+                if body.len() + n > MAX_FORM_LEN {
+                    req.into_status_response(413)?.write_all(b"Too long, nothing was saved.")?;
+                    return Ok(());
+                }
+                body.extend_from_slice(&buf[..n]);
+            }
+
+            match Credentials::from_form(&String::from_utf8_lossy(&body), config::BROADCAST_PORT) {
+                Ok(credentials) => {
+                    req.into_ok_response()?.write_all(b"Saved, the board restarts now.")?;
+                    sender.send(credentials)?;
+                }
+                Err(e) => {
+                    req.into_status_response(400)?.write_all(e.to_string().as_bytes())?;
+                }
+            }
+            Ok(())
+        }
+    })?;
+
+    // The console is the same UART the logs go to
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            };
+
+            match Credentials::from_command(&line, config::BROADCAST_PORT) {
+                Some(Ok(credentials)) => {
+                    log::info!("Settings received for {}", credentials.ssid);
+                    if sender.send(credentials).is_err() {
+                        break;
+                    }
+                }
+                Some(Err(e)) => log::warn!("Invalid settings: {}", e),
+                None => log::warn!("Expected PROVISION ssid=<name>&psk=<password>&server=<ip>&key=<hex>"),
+            }
+        }
+    });
+
+    log::info!("Setup mode: join {} and open http://192.168.71.1/ or send PROVISION over serial", ACCESS_POINT);
+
+    match receiver.recv_timeout(SETUP_TIMEOUT) {
+        Ok(credentials) => {
+            settings.store(&credentials)?;
+            // Let the page response go out before the restart
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        Err(_) => log::warn!("No settings received, restarting"),
+    }
+
+    esp_idf_svc::hal::reset::restart();
+}
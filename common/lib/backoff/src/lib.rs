@@ -0,0 +1,25 @@
+//! Exponential backoff between rounds of connection attempts.
+//!
+//! Kept out of the `wifi` crate, which only builds for the boards, so the delays are tested on
+//! Linux.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Backoff {
+    /// Delay before retry number `attempt`, counting from zero.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial.saturating_mul(1 << attempt.min(16)).min(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { initial: Duration::from_secs(1), max: Duration::from_secs(60) }
+    }
+}
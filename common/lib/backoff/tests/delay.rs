@@ -0,0 +1,34 @@
+// Delays between rounds of connection attempts.
+
+use std::time::Duration;
+
+use backoff::Backoff;
+
+const BACKOFF: Backoff = Backoff { initial: Duration::from_millis(500), max: Duration::from_secs(10) };
+
+#[test]
+fn delay_doubles_every_round() {
+    let delays: Vec<Duration> = (0..5).map(|attempt| BACKOFF.delay(attempt)).collect();
+    assert_eq!(delays, [500, 1000, 2000, 4000, 8000].map(Duration::from_millis));
+}
+
+#[test]
+fn delay_stops_at_the_cap() {
+    assert_eq!(BACKOFF.delay(5), Duration::from_secs(10));
+    assert_eq!(BACKOFF.delay(40), Duration::from_secs(10));
+    assert_eq!(BACKOFF.delay(u32::MAX), Duration::from_secs(10));
+
+    // An initial delay over the cap is capped as well
+    let backoff = Backoff { initial: Duration::from_secs(90), ..Backoff::default() };
+    assert_eq!(backoff.delay(0), Duration::from_secs(60));
+}
+
+#[test]
+fn delay_starts_over_with_the_count() {
+    // Every disconnect counts attempts from zero again, back to the initial delay
+    assert_eq!(BACKOFF.delay(u32::MAX), BACKOFF.max);
+    assert_eq!(BACKOFF.delay(0), BACKOFF.initial);
+    assert_eq!(BACKOFF.delay(1), BACKOFF.initial * 2);
+
+    assert_eq!(Backoff::default().delay(0), Duration::from_secs(1));
+}
@@ -0,0 +1,70 @@
+//! Finding the MicroBroadcast server on the local network without knowing its address.
+//!
+//! A worker broadcasts `MB DISCOVER` over UDP to [`DISCOVERY_PORT`]. The server answers with
+//! `MB SERVER <registration port>` and the worker registers with the address the answer came from.
+//! Only `std` networking is used so the same code runs on the boards and on Linux.
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const DISCOVERY_PORT: u16 = 48091;
+
+const QUERY: &str = "MB DISCOVER";
+const ANSWER: &str = "MB SERVER";
+
+/// The server side of the exchange: the reply to send for `request`, if it is a discovery query.
+pub fn answer(request: &[u8], registration_port: u16) -> Option<Vec<u8>> {
+    if request == QUERY.as_bytes() {
+        Some(format!("{} {}", ANSWER, registration_port).into_bytes())
+    } else {
+        None
+    }
+}
+
+/// Registration address announced by an answer received from `from`.
+pub fn parse_answer(answer: &[u8], from: SocketAddr) -> Option<SocketAddr> {
+    let answer = std::str::from_utf8(answer).ok()?;
+    let port = answer.strip_prefix(ANSWER)?.strip_prefix(' ')?;
+    Some(SocketAddr::new(from.ip(), u16::from_str(port).ok()?))
+}
+
+/// Send up to `attempts` queries to `target`, usually the broadcast address, waiting `timeout`
+/// for an answer after each. Returns the registration address of the first server to answer.
+pub fn discover(target: SocketAddr, timeout: Duration, attempts: usize) -> std::io::Result<Option<SocketAddr>> {
+    let bind_addr = match target {
+        SocketAddr::V4(_) => "0.0.0.0:0",
+        SocketAddr::V6(_) => "[::]:0",
+    };
+
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.set_broadcast(true)?;
+
+    let mut buffer = [0u8; 64];
+
+    for _ in 0..attempts {
+        socket.send_to(QUERY.as_bytes(), target)?;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            socket.set_read_timeout(Some(remaining))?;
+
+            match socket.recv_from(&mut buffer) {
+                Ok((n, from)) => {
+                    if let Some(server) = parse_answer(&buffer[..n], from) {
+                        return Ok(Some(server));
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    Ok(None)
+}
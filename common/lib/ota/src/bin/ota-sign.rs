@@ -0,0 +1,50 @@
+// Release keys and image signatures for firmware updates.
+//
+// ota-sign keygen                   writes a new release key to stdout, its public key to stderr
+// ota-sign public <key file>        the public key to build workers and configure the server with
+// ota-sign sign <key file> <image>  the signature to upload the image with
+//
+// Keys are 32 bytes of hex, keep the release key off the server.
+
+use std::io::Read;
+
+fn usage() -> ! {
+    eprintln!("usage: ota-sign keygen | public <key file> | sign <key file> <image>");
+    std::process::exit(2);
+}
+
+fn read_key(path: &str) -> [u8; ota::KEY_LEN] {
+    let hex = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", path, e);
+        std::process::exit(1);
+    });
+    ota::parse_hex(&hex).unwrap_or_else(|| {
+        eprintln!("{} doesn't hold a key, expected {} bytes of hex", path, ota::KEY_LEN);
+        std::process::exit(1);
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["keygen"] => {
+            let mut key = [0u8; ota::KEY_LEN];
+            std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut key)).unwrap_or_else(|e| {
+                eprintln!("No randomness: {}", e);
+                std::process::exit(1);
+            });
+            println!("{}", ota::to_hex(&key));
+            eprintln!("public key {}", ota::to_hex(&ota::public_key(&key)));
+        }
+        ["public", key] => println!("{}", ota::to_hex(&ota::public_key(&read_key(key)))),
+        ["sign", key, image] => {
+            let image = std::fs::read(image).unwrap_or_else(|e| {
+                eprintln!("Unable to read {}: {}", image, e);
+                std::process::exit(1);
+            });
+            println!("{}", ota::to_hex(&ota::sign(&read_key(key), &ota::digest(&image))));
+        }
+        _ => usage(),
+    }
+}
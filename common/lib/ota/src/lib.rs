@@ -0,0 +1,170 @@
+//! Firmware updates pushed to workers.
+//!
+//! The server tells a worker which image to run with a single directive:
+//!
+//! ```text
+//! OTA <version> <size> <sha256> <signature> <url>
+//! ```
+//!
+//! `sha256` is the hex encoded digest of the image and `signature` the hex encoded Ed25519
+//! signature of that digest by the release key, `-` for an unsigned image. The worker downloads
+//! `url` with a plain GET into the OTA partition it isn't running from, checking the image with a
+//! [`Verifier`] as it goes, and only boots it once size, digest and, if it was built with the
+//! release public key, signature all match. Without the public key the digest is all a worker has
+//! to go by, so it only takes directives sealed for its registration session. A new image has to
+//! register with the server before the worker keeps it, otherwise the previous one is booted
+//! again.
+//!
+//! Images are signed with the `ota-sign` tool of this crate:
+//!
+//! ```text
+//! cargo run --manifest-path common/lib/ota/Cargo.toml --bin ota-sign -- keygen
+//! cargo run --manifest-path common/lib/ota/Cargo.toml --bin ota-sign -- sign <key file> <image>
+//! ```
+
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier as _, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+// Written the way registration keys are
+pub use registration::{parse_hex, to_hex};
+
+pub const OTA: &str = "OTA";
+
+pub const DIGEST_LEN: usize = 32;
+pub const SIGNATURE_LEN: usize = 64;
+pub const KEY_LEN: usize = 32;
+
+// Stands in for the signature of an unsigned image
+const UNSIGNED: &str = "-";
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Update {
+    pub version: String,
+    /// Size of the image in bytes.
+    pub size: u64,
+    pub sha256: [u8; DIGEST_LEN],
+    pub signature: Option<[u8; SIGNATURE_LEN]>,
+    /// Where the worker downloads the image from.
+    pub url: String,
+}
+
+impl Update {
+
+    /// The directive for the update, without the URL it can't be sent.
+    pub fn directive(&self) -> String {
+        let signature = self.signature.map(|s| to_hex(&s)).unwrap_or_else(|| UNSIGNED.to_string());
+        format!("{} {} {} {} {} {}", OTA, self.version, self.size, to_hex(&self.sha256), signature, self.url)
+    }
+
+    /// Parse an `OTA` directive, `None` if it is something else.
+    pub fn parse(directive: &str) -> Option<Update> {
+        let mut fields = directive.split_ascii_whitespace();
+        if fields.next()? != OTA {
+            return None;
+        }
+
+        let version = fields.next()?.to_string();
+        let size = fields.next()?.parse().ok()?;
+        let sha256 = parse_hex(fields.next()?)?;
+        let signature = match fields.next()? {
+            UNSIGNED => None,
+            hex => Some(parse_hex(hex)?),
+        };
+        let url = fields.next()?.to_string();
+
+        Some(Update { version, size, sha256, signature, url })
+    }
+}
+
+/// Why an image was turned down.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Error {
+    /// More data than the update announced.
+    TooLarge,
+    /// Less data than the update announced.
+    Truncated { expected: u64, received: u64 },
+    Checksum,
+    /// The worker was built with a release key but the image isn't signed.
+    Unsigned,
+    Signature,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooLarge => write!(f, "image larger than announced"),
+            Error::Truncated { expected, received } => write!(f, "image cut short, {} of {} bytes", received, expected),
+            Error::Checksum => write!(f, "image checksum mismatch"),
+            Error::Unsigned => write!(f, "image isn't signed"),
+            Error::Signature => write!(f, "image signature invalid"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Checks an image against its update as it streams past, so it never has to be held whole.
+pub struct Verifier {
+    hasher: Sha256,
+    received: u64,
+    size: u64,
+    sha256: [u8; DIGEST_LEN],
+    signature: Option<[u8; SIGNATURE_LEN]>,
+}
+
+impl Verifier {
+
+    pub fn new(update: &Update) -> Self {
+        Verifier { hasher: Sha256::new(), received: 0, size: update.size, sha256: update.sha256, signature: update.signature }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.received += chunk.len() as u64;
+        if self.received > self.size {
+            return Err(Error::TooLarge);
+        }
+        self.hasher.update(chunk);
+        Ok(())
+    }
+
+    /// Whether the whole image arrived intact, and is signed by `public_key` if given.
+    pub fn finish(self, public_key: Option<&[u8; KEY_LEN]>) -> Result<(), Error> {
+        if self.received != self.size {
+            return Err(Error::Truncated { expected: self.size, received: self.received });
+        }
+        let digest: [u8; DIGEST_LEN] = self.hasher.finalize().into();
+        if digest != self.sha256 {
+            return Err(Error::Checksum);
+        }
+
+        match (public_key, self.signature) {
+            (None, _) => Ok(()),
+            (Some(_), None) => Err(Error::Unsigned),
+            (Some(public_key), Some(signature)) if verify(public_key, &digest, &signature) => Ok(()),
+            (Some(_), Some(_)) => Err(Error::Signature),
+        }
+    }
+}
+
+pub fn digest(image: &[u8]) -> [u8; DIGEST_LEN] {
+    Sha256::digest(image).into()
+}
+
+/// Sign the digest of an image with the release key.
+pub fn sign(signing_key: &[u8; KEY_LEN], digest: &[u8; DIGEST_LEN]) -> [u8; SIGNATURE_LEN] {
+    SigningKey::from_bytes(signing_key).sign(digest).to_bytes()
+}
+
+/// The public half of the release key, for workers and the server to check signatures with.
+pub fn public_key(signing_key: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    SigningKey::from_bytes(signing_key).verifying_key().to_bytes()
+}
+
+pub fn verify(public_key: &[u8; KEY_LEN], digest: &[u8; DIGEST_LEN], signature: &[u8; SIGNATURE_LEN]) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key.verify(digest, &Signature::from_bytes(signature)).is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,81 @@
+// Update directives, and images checked as they stream in.
+
+use ota::{Error, Update, Verifier};
+
+const RELEASE_KEY: [u8; ota::KEY_LEN] = [7; ota::KEY_LEN];
+
+fn image() -> Vec<u8> {
+    (0..5000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn update(image: &[u8], signed: bool) -> Update {
+    let sha256 = ota::digest(image);
+    Update {
+        version: "0.2.0".to_string(),
+        size: image.len() as u64,
+        sha256,
+        signature: signed.then(|| ota::sign(&RELEASE_KEY, &sha256)),
+        url: "http://192.168.4.209:8091/firmware/0.2.0".to_string(),
+    }
+}
+
+fn stream(update: &Update, image: &[u8], public_key: Option<&[u8; ota::KEY_LEN]>) -> Result<(), Error> {
+    let mut verifier = Verifier::new(update);
+    for chunk in image.chunks(1024) {
+        verifier.update(chunk)?;
+    }
+    verifier.finish(public_key)
+}
+
+#[test]
+fn directive_round_trips() {
+    let signed = update(&image(), true);
+    assert!(signed.directive().starts_with("OTA 0.2.0 5000 "));
+    assert_eq!(Update::parse(&signed.directive()), Some(signed));
+
+    let unsigned = update(&image(), false);
+    assert!(unsigned.directive().contains(" - http://"));
+    assert_eq!(Update::parse(&unsigned.directive()), Some(unsigned));
+}
+
+#[test]
+fn other_directives_are_not_updates() {
+    assert_eq!(Update::parse("MESSAGE OTA"), None);
+    assert_eq!(Update::parse("OTA 0.2.0 5000 abcd - http://x/"), None);
+    assert_eq!(Update::parse("OTA 0.2.0"), None);
+}
+
+#[test]
+fn intact_images_pass() {
+    let public_key = ota::public_key(&RELEASE_KEY);
+
+    assert_eq!(stream(&update(&image(), true), &image(), Some(&public_key)), Ok(()));
+    // Workers built without a release key only check the digest
+    assert_eq!(stream(&update(&image(), false), &image(), None), Ok(()));
+}
+
+#[test]
+fn damaged_images_fail() {
+    let public_key = ota::public_key(&RELEASE_KEY);
+    let update = update(&image(), true);
+
+    let mut flipped = image();
+    flipped[1234] ^= 1;
+    assert_eq!(stream(&update, &flipped, Some(&public_key)), Err(Error::Checksum));
+
+    assert_eq!(stream(&update, &image()[..4000], None), Err(Error::Truncated { expected: 5000, received: 4000 }));
+
+    let mut longer = image();
+    longer.push(0);
+    assert_eq!(stream(&update, &longer, None), Err(Error::TooLarge));
+}
+
+#[test]
+fn signatures_are_required_with_a_release_key() {
+    let public_key = ota::public_key(&RELEASE_KEY);
+
+    assert_eq!(stream(&update(&image(), false), &image(), Some(&public_key)), Err(Error::Unsigned));
+
+    let other_key = ota::public_key(&[8; ota::KEY_LEN]);
+    assert_eq!(stream(&update(&image(), true), &image(), Some(&other_key)), Err(Error::Signature));
+}
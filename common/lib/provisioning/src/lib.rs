@@ -0,0 +1,162 @@
+//! Network settings a worker is given at runtime instead of at build time.
+//!
+//! Settings reach a board either from the form on its setup page or as a serial command:
+//!
+//! ```text
+//! PROVISION ssid=Classroom&psk=hunter22&server=192.168.4.209
+//! ```
+//!
+//! Both use the same `application/x-www-form-urlencoded` fields. `psk` may be left empty for open
+//! networks and `server` may be left out entirely, the worker then relies on discovery. `key` is
+//! the optional hex registration key the server knows the board by. Nothing here touches the
+//! hardware so it can be checked on the host.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+const COMMAND: &str = "PROVISION";
+
+const MAX_SSID_LEN: usize = 32;
+const MIN_PSK_LEN: usize = 8;
+const MAX_PSK_LEN: usize = 63;
+// A raw 256 bit key is given as hex instead of a passphrase
+const HEX_PSK_LEN: usize = 64;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Credentials {
+    pub ssid: String,
+    pub psk: String,
+    pub server: Option<SocketAddr>,
+    pub key: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum CredentialError {
+    MissingSsid,
+    SsidTooLong,
+    InvalidPsk,
+    InvalidServer(String),
+    InvalidKey,
+    InvalidEncoding,
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CredentialError::MissingSsid => write!(f, "WiFi name is missing"),
+            CredentialError::SsidTooLong => write!(f, "WiFi name is longer than {} bytes", MAX_SSID_LEN),
+            CredentialError::InvalidPsk => write!(f, "WiFi password must be empty, {} to {} characters or {} hex digits", MIN_PSK_LEN, MAX_PSK_LEN, HEX_PSK_LEN),
+            CredentialError::InvalidServer(s) => write!(f, "Invalid server address {}", s),
+            CredentialError::InvalidKey => write!(f, "Registration key must be at least 16 bytes of hex"),
+            CredentialError::InvalidEncoding => write!(f, "Malformed form data"),
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+impl Credentials {
+
+    /// Validated credentials. An empty `psk` means an open network.
+    pub fn new(ssid: &str, psk: &str, server: Option<SocketAddr>) -> Result<Self, CredentialError> {
+        if ssid.is_empty() {
+            return Err(CredentialError::MissingSsid);
+        }
+        if ssid.len() > MAX_SSID_LEN {
+            return Err(CredentialError::SsidTooLong);
+        }
+        if !valid_psk(psk) {
+            return Err(CredentialError::InvalidPsk);
+        }
+
+        Ok(Credentials { ssid: ssid.to_string(), psk: psk.to_string(), server, key: None })
+    }
+
+    /// Parse the fields posted by the setup page. A server given without a port registers on
+    /// `default_port`.
+    pub fn from_form(body: &str, default_port: u16) -> Result<Self, CredentialError> {
+        let mut ssid = None;
+        let mut psk = String::new();
+        let mut server = None;
+        let mut key = None;
+
+        for field in body.trim().split('&').filter(|f| !f.is_empty()) {
+            let (name, value) = field.split_once('=').unwrap_or((field, ""));
+            let value = url_decode(value)?;
+            match name {
+                "ssid"   => ssid = Some(value),
+                "psk"    => psk = value,
+                "server" => server = parse_server(&value, default_port)?,
+                "key"    => key = parse_key(&value)?,
+                _ => (),
+            }
+        }
+
+        let mut credentials = Credentials::new(&ssid.unwrap_or_default(), &psk, server)?;
+        credentials.key = key;
+        Ok(credentials)
+    }
+
+    /// Parse a `PROVISION <fields>` line received over serial. Returns `None` for any other line.
+    pub fn from_command(line: &str, default_port: u16) -> Option<Result<Self, CredentialError>> {
+        let fields = line.trim().strip_prefix(COMMAND)?;
+        if !fields.is_empty() && !fields.starts_with(' ') {
+            return None;
+        }
+        Some(Credentials::from_form(fields, default_port))
+    }
+}
+
+/// `<ip>` or `<ip>:<port>`. Empty means no server.
+pub fn parse_server(value: &str, default_port: u16) -> Result<Option<SocketAddr>, CredentialError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(addr) = SocketAddr::from_str(value) {
+        return Ok(Some(addr));
+    }
+
+    IpAddr::from_str(value)
+        .map(|ip| Some(SocketAddr::new(ip, default_port)))
+        .map_err(|_| CredentialError::InvalidServer(value.to_string()))
+}
+
+/// Hex registration key. Empty means no key.
+pub fn parse_key(value: &str) -> Result<Option<Vec<u8>>, CredentialError> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    registration::parse_key(value).map(Some).ok_or(CredentialError::InvalidKey)
+}
+
+fn valid_psk(psk: &str) -> bool {
+    if psk.is_empty() {
+        return true;
+    }
+    if psk.len() == HEX_PSK_LEN && psk.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return true;
+    }
+    (MIN_PSK_LEN..=MAX_PSK_LEN).contains(&psk.len()) && psk.bytes().all(|b| (0x20..=0x7e).contains(&b))
+}
+
+fn url_decode(value: &str) -> Result<String, CredentialError> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next().ok_or(CredentialError::InvalidEncoding)?, input.next().ok_or(CredentialError::InvalidEncoding)?];
+                let hex = std::str::from_utf8(&hex).map_err(|_| CredentialError::InvalidEncoding)?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| CredentialError::InvalidEncoding)?);
+            }
+            b => bytes.push(b),
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| CredentialError::InvalidEncoding)
+}
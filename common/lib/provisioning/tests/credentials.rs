@@ -0,0 +1,71 @@
+use std::net::SocketAddr;
+
+use provisioning::{CredentialError, Credentials};
+
+const PORT: u16 = 8092;
+
+#[test]
+fn form_with_all_fields() {
+    let credentials = Credentials::from_form("ssid=Class+Room&psk=hunter22&server=192.168.4.209", PORT).unwrap();
+
+    assert_eq!(credentials.ssid, "Class Room");
+    assert_eq!(credentials.psk, "hunter22");
+    assert_eq!(credentials.server, Some(SocketAddr::from(([192, 168, 4, 209], PORT))));
+}
+
+#[test]
+fn percent_encoded_values() {
+    let credentials = Credentials::from_form("ssid=Caf%C3%A9&psk=p%26ss%3Dw0rd", PORT).unwrap();
+
+    assert_eq!(credentials.ssid, "Café");
+    assert_eq!(credentials.psk, "p&ss=w0rd");
+}
+
+#[test]
+fn open_network_without_server() {
+    let credentials = Credentials::from_form("ssid=Guest&psk=&server=", PORT).unwrap();
+
+    assert_eq!(credentials.psk, "");
+    assert_eq!(credentials.server, None);
+}
+
+#[test]
+fn server_with_port() {
+    let credentials = Credentials::from_form("ssid=Guest&server=10.0.0.2:9000", PORT).unwrap();
+    assert_eq!(credentials.server, Some(SocketAddr::from(([10, 0, 0, 2], 9000))));
+}
+
+#[test]
+fn invalid_fields_are_rejected() {
+    assert_eq!(Credentials::from_form("psk=hunter22", PORT), Err(CredentialError::MissingSsid));
+    assert_eq!(Credentials::from_form(&format!("ssid={}", "x".repeat(33)), PORT), Err(CredentialError::SsidTooLong));
+    assert_eq!(Credentials::from_form("ssid=Guest&psk=short", PORT), Err(CredentialError::InvalidPsk));
+    assert_eq!(Credentials::from_form("ssid=Guest&server=nope", PORT), Err(CredentialError::InvalidServer("nope".to_string())));
+    assert_eq!(Credentials::from_form("ssid=Guest%2", PORT), Err(CredentialError::InvalidEncoding));
+}
+
+#[test]
+fn hex_key_is_accepted() {
+    let key = "0123456789abcdef".repeat(4);
+    assert!(Credentials::from_form(&format!("ssid=Guest&psk={}", key), PORT).is_ok());
+    assert_eq!(Credentials::from_form(&format!("ssid=Guest&psk={}g", &key[1..]), PORT), Err(CredentialError::InvalidPsk));
+}
+
+#[test]
+fn serial_command() {
+    let credentials = Credentials::from_command("PROVISION ssid=Guest&psk=hunter22\r\n", PORT).unwrap().unwrap();
+    assert_eq!(credentials.ssid, "Guest");
+
+    assert_eq!(Credentials::from_command("PROVISIONED", PORT), None);
+    assert_eq!(Credentials::from_command("hello", PORT), None);
+    assert_eq!(Credentials::from_command("PROVISION", PORT), Some(Err(CredentialError::MissingSsid)));
+}
+
+#[test]
+fn registration_key() {
+    let credentials = Credentials::from_form("ssid=Guest&key=00112233445566778899aabbccddeeff", PORT).unwrap();
+    assert_eq!(credentials.key, Some((0..16).map(|i| i * 0x11).collect()));
+
+    assert_eq!(Credentials::from_form("ssid=Guest&key=", PORT).unwrap().key, None);
+    assert_eq!(Credentials::from_form("ssid=Guest&key=0011", PORT), Err(CredentialError::InvalidKey));
+}
@@ -0,0 +1,192 @@
+//! The exchange a worker goes through to register with the server.
+//!
+//! Every message is a single line:
+//!
+//! ```text
+//! worker: REGISTER <mac> [port]
+//! server: CHALLENGE <nonce>           only for workers the registry holds a key for
+//! worker: RESPONSE <hmac>
+//! server: ACCEPTED | REJECTED
+//! ```
+//!
+//! The response is the hex encoded HMAC-SHA256 of the nonce followed by the MAC address, keyed
+//! with the worker's pre-shared key. Keys are written as hex as well.
+//!
+//! After an accepted challenge both sides hold a [`Session`], and every directive the server
+//! sends is sealed with it, as is every line the worker answers with, its first report included:
+//!
+//! ```text
+//! SEALED <counter> <ciphertext>
+//! ```
+//!
+//! The ciphertext is hex encoded ChaCha20-Poly1305 under a key derived from the pre-shared key,
+//! the nonce and the MAC address, so it is fresh for every registration. The counter makes up the
+//! AEAD nonce together with the direction, and only ever grows each way, which keeps old
+//! directives and replies from being replayed.
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const REGISTER: &str = "REGISTER";
+pub const CHALLENGE: &str = "CHALLENGE";
+pub const RESPONSE: &str = "RESPONSE";
+pub const ACCEPTED: &str = "ACCEPTED";
+pub const REJECTED: &str = "REJECTED";
+pub const SEALED: &str = "SEALED";
+
+pub const NONCE_LEN: usize = 16;
+
+// Anything shorter is too easy to guess
+const MIN_KEY_LEN: usize = 16;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The opening line of a worker, `port` is left out by workers listening on the default port.
+pub fn request(mac_address: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) => format!("{} {} {}\n", REGISTER, mac_address, port),
+        None => format!("{} {}\n", REGISTER, mac_address),
+    }
+}
+
+pub fn challenge(nonce: &[u8]) -> String {
+    format!("{} {}\n", CHALLENGE, to_hex(nonce))
+}
+
+/// The nonce of a `CHALLENGE` line.
+pub fn parse_challenge(line: &str) -> Option<Vec<u8>> {
+    from_hex(line.trim().strip_prefix(CHALLENGE)?.strip_prefix(' ')?)
+}
+
+/// The worker's answer to `nonce`.
+pub fn response(key: &[u8], nonce: &[u8], mac_address: &str) -> String {
+    let digest = signer(key, nonce, mac_address).finalize().into_bytes();
+    format!("{} {}\n", RESPONSE, to_hex(&digest))
+}
+
+/// Check a `RESPONSE` line in constant time.
+pub fn verify(key: &[u8], nonce: &[u8], mac_address: &str, line: &str) -> bool {
+    let Some(digest) = line.trim().strip_prefix(RESPONSE).and_then(|d| d.strip_prefix(' ')).and_then(from_hex) else {
+        return false;
+    };
+    signer(key, nonce, mac_address).verify_slice(&digest).is_ok()
+}
+
+/// Encryption of what goes between the server and one registered worker.
+pub struct Session {
+    cipher: ChaCha20Poly1305,
+    // Last counter sealed, or opened, each way
+    directives: u64,
+    replies: u64,
+}
+
+// Which way a line goes, part of the AEAD nonce so directives and replies never share one
+const DIRECTIVES: u8 = 0;
+const REPLIES: u8 = 1;
+
+impl Session {
+
+    /// Both sides derive the same session from the key and the nonce of the challenge.
+    pub fn new(key: &[u8], nonce: &[u8], mac_address: &str) -> Self {
+        let mut derive = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+        derive.update(b"MB SESSION");
+        derive.update(nonce);
+        derive.update(mac_address.as_bytes());
+        let session_key = derive.finalize().into_bytes();
+
+        Session { cipher: ChaCha20Poly1305::new(Key::from_slice(&session_key)), directives: 0, replies: 0 }
+    }
+
+    pub fn seal(&mut self, directive: &str) -> String {
+        self.directives += 1;
+        seal_line(&self.cipher, DIRECTIVES, self.directives, directive)
+    }
+
+    /// The directive inside a sealed one. `None` if it isn't sealed, was tampered with, was
+    /// sealed for another session or was seen before.
+    pub fn open(&mut self, sealed: &str) -> Option<String> {
+        open_line(&self.cipher, DIRECTIVES, &mut self.directives, sealed)
+    }
+
+    /// A line of the worker's answer to a directive, sealed on a line of its own.
+    pub fn seal_reply(&mut self, line: &str) -> String {
+        self.replies += 1;
+        format!("{}\n", seal_line(&self.cipher, REPLIES, self.replies, line))
+    }
+
+    /// The line inside a sealed reply line, `None` as for [`Session::open`].
+    pub fn open_reply(&mut self, sealed: &str) -> Option<String> {
+        open_line(&self.cipher, REPLIES, &mut self.replies, sealed)
+    }
+}
+
+/// How a worker answers with `line`: sealed once it holds a session, as it is otherwise.
+pub fn reply(session: Option<&mut Session>, line: &str) -> String {
+    match session {
+        Some(session) => session.seal_reply(line),
+        None => line.to_string(),
+    }
+}
+
+fn seal_line(cipher: &ChaCha20Poly1305, direction: u8, counter: u64, plaintext: &str) -> String {
+    let ciphertext = cipher.encrypt(&aead_nonce(direction, counter), plaintext.as_bytes())
+        .expect("ChaCha20-Poly1305 encrypts lines of any length");
+    format!("{} {} {}", SEALED, counter, to_hex(&ciphertext))
+}
+
+fn open_line(cipher: &ChaCha20Poly1305, direction: u8, last: &mut u64, sealed: &str) -> Option<String> {
+    let mut parts = sealed.trim().splitn(3, ' ');
+    if parts.next()? != SEALED {
+        return None;
+    }
+    let counter: u64 = parts.next()?.parse().ok()?;
+    let ciphertext = from_hex(parts.next()?)?;
+
+    if counter <= *last {
+        return None;
+    }
+
+    let plaintext = cipher.decrypt(&aead_nonce(direction, counter), ciphertext.as_slice()).ok()?;
+    *last = counter;
+    String::from_utf8(plaintext).ok()
+}
+
+fn aead_nonce(direction: u8, counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+/// Decode a pre-shared key given as hex.
+pub fn parse_key(hex: &str) -> Option<Vec<u8>> {
+    from_hex(hex.trim()).filter(|k| k.len() >= MIN_KEY_LEN)
+}
+
+fn signer(key: &[u8], nonce: &[u8], mac_address: &str) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(nonce);
+    mac.update(mac_address.as_bytes());
+    mac
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode hex of exactly `N` bytes, as digests and signatures are written.
+pub fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    from_hex(hex.trim())?.try_into().ok()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
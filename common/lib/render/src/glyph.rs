@@ -0,0 +1,406 @@
+//! Which glyph draws each character of a message.
+//!
+//! Text is drawn with the ISO 8859 fonts of `embedded_graphics`: Latin-1 covers most names, and
+//! Latin-9 and Latin-2 fill in what it lacks (€, Œ, Š, Ł, Ő, Ř and friends). Emoji, and
+//! shortcodes such as `:heart:`, are drawn as one of the built-in [`Icon`]s. Anything else
+//! becomes `?`.
+//!
+//! [`spans`] only works out the mapping, so it can be checked on the host without a display.
+
+use embedded_graphics::{
+    mono_font::{iso_8859_1, iso_8859_15, iso_8859_2, mapping, mapping::StrGlyphMapping, MonoFont, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Baseline, Text},
+};
+
+/// Height of a line of message text, in pixels.
+pub const LINE_HEIGHT: u32 = 13;
+
+/// Icons are square and as wide as two characters.
+pub const ICON_SIZE: u32 = 12;
+
+/// The font family a character is drawn with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Charset {
+    Latin1,
+    Latin9,
+    Latin2,
+}
+
+impl Charset {
+
+    /// The first charset with a glyph for `c`, or `None` if there is none or `c` is a control
+    /// character.
+    pub fn for_char(c: char) -> Option<Charset> {
+        if c.is_control() {
+            return None;
+        }
+
+        [Charset::Latin1, Charset::Latin9, Charset::Latin2].into_iter()
+            .find(|charset| charset.mapping().contains(c))
+    }
+
+    /// The 6x13 font, the size used for messages.
+    pub fn font(self) -> &'static MonoFont<'static> {
+        match self {
+            Charset::Latin1 => &iso_8859_1::FONT_6X13,
+            Charset::Latin9 => &iso_8859_15::FONT_6X13,
+            Charset::Latin2 => &iso_8859_2::FONT_6X13,
+        }
+    }
+
+    fn mapping(self) -> &'static StrGlyphMapping<'static> {
+        match self {
+            Charset::Latin1 => &mapping::ISO_8859_1,
+            Charset::Latin9 => &mapping::ISO_8859_15,
+            Charset::Latin2 => &mapping::ISO_8859_2,
+        }
+    }
+}
+
+/// A built-in picture that fits in a line of text.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Icon {
+    Heart,
+    Smile,
+    Laugh,
+    Wink,
+    Sad,
+    Star,
+    Check,
+    Cross,
+    ThumbsUp,
+    Note,
+    Sun,
+    Bell,
+}
+
+impl Icon {
+
+    pub const ALL: [Icon; 12] = [
+        Icon::Heart, Icon::Smile, Icon::Laugh, Icon::Wink, Icon::Sad, Icon::Star,
+        Icon::Check, Icon::Cross, Icon::ThumbsUp, Icon::Note, Icon::Sun, Icon::Bell,
+    ];
+
+    /// The shortcode name, `heart` for `:heart:`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Icon::Heart    => "heart",
+            Icon::Smile    => "smile",
+            Icon::Laugh    => "laugh",
+            Icon::Wink     => "wink",
+            Icon::Sad      => "sad",
+            Icon::Star     => "star",
+            Icon::Check    => "check",
+            Icon::Cross    => "cross",
+            Icon::ThumbsUp => "thumbsup",
+            Icon::Note     => "note",
+            Icon::Sun      => "sun",
+            Icon::Bell     => "bell",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Icon> {
+        Icon::ALL.into_iter().find(|icon| icon.name() == name)
+    }
+
+    /// The icon drawn in place of an emoji.
+    pub fn from_char(c: char) -> Option<Icon> {
+        match c {
+            '❤' | '♥' | '💕' | '💖' | '💗' | '💙' | '💚' | '💛' | '💜' | '🧡' | '😍' | '🥰' => Some(Icon::Heart),
+            '🙂' | '😀' | '😃' | '😄' | '😊' | '☺' | '😁' => Some(Icon::Smile),
+            '😂' | '🤣' | '😆' => Some(Icon::Laugh),
+            '😉' => Some(Icon::Wink),
+            '🙁' | '☹' | '😞' | '😟' | '😢' | '😭' => Some(Icon::Sad),
+            '⭐' | '🌟' | '★' => Some(Icon::Star),
+            '✔' | '✅' | '☑' => Some(Icon::Check),
+            '❌' | '✖' => Some(Icon::Cross),
+            '👍' => Some(Icon::ThumbsUp),
+            '♪' | '♫' | '🎵' | '🎶' => Some(Icon::Note),
+            '☀' | '🌞' => Some(Icon::Sun),
+            '🔔' => Some(Icon::Bell),
+            _ => None,
+        }
+    }
+
+    /// Rows of the picture, `#` for a lit pixel.
+    pub fn rows(self) -> &'static [&'static str; ICON_SIZE as usize] {
+        match self {
+            Icon::Heart => &[
+                "............",
+                "..##....##..",
+                ".####..####.",
+                "############",
+                "############",
+                "############",
+                ".##########.",
+                "..########..",
+                "...######...",
+                "....####....",
+                ".....##.....",
+                "............",
+            ],
+            Icon::Smile => &[
+                "...######...",
+                "..#......#..",
+                ".#........#.",
+                "#..##..##..#",
+                "#..##..##..#",
+                "#..........#",
+                "#..........#",
+                "#.#......#.#",
+                "#..#....#..#",
+                ".#..####..#.",
+                "..#......#..",
+                "...######...",
+            ],
+            Icon::Laugh => &[
+                "...######...",
+                "..#......#..",
+                ".#.#....#.#.",
+                "#.#.#..#.#.#",
+                "#..........#",
+                "#.########.#",
+                "#.#......#.#",
+                "#..#....#..#",
+                "#...####...#",
+                ".#........#.",
+                "..#......#..",
+                "...######...",
+            ],
+            Icon::Wink => &[
+                "...######...",
+                "..#......#..",
+                ".#........#.",
+                "#..##......#",
+                "#..##..###.#",
+                "#..........#",
+                "#..........#",
+                "#.#......#.#",
+                "#..#....#..#",
+                ".#..####..#.",
+                "..#......#..",
+                "...######...",
+            ],
+            Icon::Sad => &[
+                "...######...",
+                "..#......#..",
+                ".#........#.",
+                "#..##..##..#",
+                "#..##..##..#",
+                "#..........#",
+                "#..........#",
+                "#...####...#",
+                "#..#....#..#",
+                ".#.#....#.#.",
+                "..#......#..",
+                "...######...",
+            ],
+            Icon::Star => &[
+                ".....##.....",
+                ".....##.....",
+                "....####....",
+                "....####....",
+                "############",
+                ".##########.",
+                "..########..",
+                "...######...",
+                "...######...",
+                "..###..###..",
+                "..##....##..",
+                ".#........#.",
+            ],
+            Icon::Check => &[
+                "............",
+                "............",
+                "..........##",
+                ".........##.",
+                "........##..",
+                "##.....##...",
+                ".##...##....",
+                "..##.##.....",
+                "...###......",
+                "....#.......",
+                "............",
+                "............",
+            ],
+            Icon::Cross => &[
+                "............",
+                ".##......##.",
+                ".###....###.",
+                "..###..###..",
+                "...######...",
+                "....####....",
+                "....####....",
+                "...######...",
+                "..###..###..",
+                ".###....###.",
+                ".##......##.",
+                "............",
+            ],
+            Icon::ThumbsUp => &[
+                ".....##.....",
+                "....#.#.....",
+                "....#.#.....",
+                "...#..#.....",
+                "##.#..#####.",
+                "#.#.......#.",
+                "#.#......##.",
+                "#.#.......#.",
+                "#.#......##.",
+                "#.#.......#.",
+                "##.#######..",
+                "............",
+            ],
+            Icon::Note => &[
+                "....######..",
+                "....#....#..",
+                "....######..",
+                "....#....#..",
+                "....#....#..",
+                "....#....#..",
+                "....#....#..",
+                "..###..###..",
+                ".####.####..",
+                ".####.####..",
+                "..##...##...",
+                "............",
+            ],
+            Icon::Sun => &[
+                ".....##.....",
+                ".#...##...#.",
+                "..#......#..",
+                "....####....",
+                "...######...",
+                "##.######.##",
+                "##.######.##",
+                "...######...",
+                "....####....",
+                "..#......#..",
+                ".#...##...#.",
+                ".....##.....",
+            ],
+            Icon::Bell => &[
+                ".....##.....",
+                "....####....",
+                "...#....#...",
+                "..#......#..",
+                "..#......#..",
+                "..#......#..",
+                "..#......#..",
+                ".#........#.",
+                "############",
+                "............",
+                ".....##.....",
+                "............",
+            ],
+        }
+    }
+}
+
+/// A piece of a message that is drawn in one go.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Span {
+    Text(String, Charset),
+    Icon(Icon),
+    Newline,
+}
+
+/// Split `text` into runs of characters sharing a font, icons and line breaks.
+pub fn spans(text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some((icon, len)) = shortcode(rest) {
+            spans.push(Span::Icon(icon));
+            rest = &rest[len..];
+            continue;
+        }
+        rest = &rest[c.len_utf8()..];
+
+        match c {
+            '\n' => spans.push(Span::Newline),
+            // Joiners and emoji presentation selectors have nothing to draw
+            '\u{200B}'..='\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' => (),
+            _ if c.is_control() => (),
+            _ => match Icon::from_char(c) {
+                Some(icon) => spans.push(Span::Icon(icon)),
+                None => {
+                    let (c, charset) = match Charset::for_char(c) {
+                        Some(charset) => (c, charset),
+                        None => ('?', Charset::Latin1),
+                    };
+
+                    match spans.last_mut() {
+                        Some(Span::Text(run, run_charset)) if *run_charset == charset => run.push(c),
+                        _ => spans.push(Span::Text(c.to_string(), charset)),
+                    }
+                }
+            },
+        }
+    }
+
+    spans
+}
+
+// `:name:` at the start of `text`, with the length it takes up
+fn shortcode(text: &str) -> Option<(Icon, usize)> {
+    let name = text.strip_prefix(':')?;
+    let end = name.find(':')?;
+    let icon = Icon::from_name(&name[..end])?;
+    Some((icon, end + 2))
+}
+
+/// Draw `text` from `position` down, clearing behind it with `background` if given. Returns
+/// where the next character would go.
+pub fn draw_text<D>(target: &mut D, text: &str, position: Point, background: Option<BinaryColor>) -> Result<Point, D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let mut cursor = position;
+
+    for span in spans(text) {
+        match span {
+            Span::Text(run, charset) => {
+                let mut text_style = MonoTextStyleBuilder::new()
+                    .font(charset.font())
+                    .text_color(BinaryColor::On);
+                if let Some(background) = background {
+                    text_style = text_style.background_color(background);
+                }
+
+                cursor = Text::with_baseline(&run, cursor, text_style.build(), Baseline::Top)
+                    .draw(target)?;
+            }
+            Span::Icon(icon) => {
+                draw_icon(target, icon, cursor, background)?;
+                cursor.x += ICON_SIZE as i32;
+            }
+            Span::Newline => cursor = Point::new(position.x, cursor.y + LINE_HEIGHT as i32),
+        }
+    }
+
+    Ok(cursor)
+}
+
+pub fn draw_icon<D>(target: &mut D, icon: Icon, top_left: Point, background: Option<BinaryColor>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if let Some(background) = background {
+        Rectangle::new(top_left, Size::new(ICON_SIZE, LINE_HEIGHT))
+            .into_styled(PrimitiveStyle::with_fill(background))
+            .draw(target)?;
+    }
+
+    let pixels = icon.rows().iter().enumerate().flat_map(|(y, row)| {
+        row.bytes().enumerate()
+            .filter(|(_, b)| *b == b'#')
+            .map(move |(x, _)| Pixel(top_left + Point::new(x as i32, y as i32), BinaryColor::On))
+    });
+
+    target.draw_iter(pixels)
+}
@@ -0,0 +1,170 @@
+//! Everything a worker draws on its screen, independent of the display hardware.
+//!
+//! The functions here are generic over any `DrawTarget<Color = BinaryColor>`, so the client
+//! drives them with the SSD1306 and the simulator with an in-memory frame.
+
+pub mod glyph;
+
+use std::str::FromStr;
+
+use embedded_graphics::{
+    mono_font::{ascii::{FONT_6X10, FONT_10X20}, MonoTextStyleBuilder},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{PrimitiveStyle, Rectangle, Sector},
+    text::{Baseline, Text},
+};
+
+/// Animations every worker carries, by the names directives use.
+pub const ANIMATIONS: [&str; 3] = ["CartoonEyes", "Heart", "Unicorn"];
+
+/// A screen requested by a directive from the server.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Screen {
+    Message(String),
+    Timer { current: u64, total: u64 },
+    Animation(String),
+    Scene { animation: Option<String>, overlay: Overlay },
+}
+
+impl Screen {
+
+    /// Parse a directive such as `MESSAGE Hello` or `TIMER 30/60`. Returns `None` for `PING`
+    /// and anything that isn't understood.
+    pub fn parse(directive: &str) -> Option<Screen> {
+        match directive.split_once(' ') {
+            Some(("ANIMATE", a)) => Some(Screen::Animation(a.to_string())),
+            Some(("MESSAGE", m)) => Some(Screen::Message(m.to_string())),
+            Some(("TIMER", t))   => parse_timer(t).map(|(current, total)| Screen::Timer { current, total }),
+            Some(("SCENE", s))   => parse_scene(s),
+            _ => None,
+        }
+    }
+}
+
+/// Layers drawn on top of the running animation.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Overlay {
+    pub text: Option<String>,
+    pub timer: Option<u64>,
+}
+
+impl Overlay {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.timer.is_none()
+    }
+}
+
+// <remaining>/<total>, both in seconds
+fn parse_timer(timer: &str) -> Option<(u64, u64)> {
+    let (current, total) = timer.split_once('/')?;
+    Some((u64::from_str(current).ok()?, u64::from_str(total).ok()?))
+}
+
+// <animation>|<remaining>/<total>|<text>
+//
+// Any layer may be left empty. The text comes last so it is free to contain '|'.
+fn parse_scene(scene: &str) -> Option<Screen> {
+    let mut layers = scene.splitn(3, '|');
+
+    let animation = layers.next()
+        .filter(|a| !a.is_empty())
+        .map(|a| a.to_string());
+
+    let timer = match layers.next().filter(|t| !t.is_empty()) {
+        Some(t) => Some(parse_timer(t)?.0),
+        None => None,
+    };
+
+    let text = layers.next()
+        .filter(|m| !m.is_empty())
+        .map(|m| m.to_string());
+
+    Some(Screen::Scene { animation, overlay: Overlay { text, timer } })
+}
+
+pub fn draw_message<D>(target: &mut D, message: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    glyph::draw_text(target, message, Point::new(0, 0), None)?;
+
+    Ok(())
+}
+
+pub fn draw_timer<D>(target: &mut D, current: u64, total: u64) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let text_style = MonoTextStyleBuilder::new()
+        .font(&FONT_10X20)
+        .text_color(BinaryColor::On)
+        .build();
+
+    let ratio = 360.0 * current as f32 / total as f32;
+
+    target.clear(BinaryColor::Off)?;
+
+    Text::with_baseline(&current.to_string(), Point::new(0, 0), text_style, Baseline::Top)
+        .draw(target)?;
+
+    Text::with_baseline(&total.to_string(), Point::new(0, 22), text_style, Baseline::Top)
+        .draw(target)?;
+
+    // Circle Outline
+    Sector::new(Point::new(65, 1), 60, -90.0.deg(), 360.0.deg())
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(target)?;
+
+    if ratio > 0.0 {
+        // Circle Fill
+        Sector::new(Point::new(65, 1), 60, -90.0.deg(), ratio.deg())
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+
+    } else {
+
+        Text::with_baseline("Done!", Point::new(0, 44), text_style, Baseline::Top)
+            .draw(target)?;
+    }
+
+    Ok(())
+}
+
+/// Draw the overlay layers without clearing what is already on the target.
+pub fn draw_overlay<D>(target: &mut D, overlay: &Overlay) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if let Some(text) = &overlay.text {
+        // Clear behind the glyphs so the text stays readable over the animation
+        glyph::draw_text(target, text, Point::new(0, 0), Some(BinaryColor::Off))?;
+    }
+
+    if let Some(remaining) = overlay.timer {
+        let label = if remaining > 0 {
+            format!("{}:{:02}", remaining / 60, remaining % 60)
+        } else {
+            "Done!".to_string()
+        };
+
+        // Inverted badge in the bottom right corner
+        let badge_size = Size::new(label.len() as u32 * 6 + 4, 12);
+        let screen = target.bounding_box();
+        let badge = Rectangle::new(screen.bottom_right().unwrap() - Size::new(badge_size.width - 1, badge_size.height - 1), badge_size);
+
+        badge.into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+
+        let label_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(BinaryColor::Off)
+            .build();
+
+        Text::with_baseline(&label, badge.top_left + Point::new(2, 1), label_style, Baseline::Top)
+            .draw(target)?;
+    }
+
+    Ok(())
+}
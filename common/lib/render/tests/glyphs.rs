@@ -0,0 +1,84 @@
+// How message text maps onto fonts and icons.
+
+use embedded_graphics::{mock_display::MockDisplay, pixelcolor::BinaryColor, prelude::*};
+
+use render::glyph::{self, Charset, Icon, Span};
+
+fn text(run: &str, charset: Charset) -> Span {
+    Span::Text(run.to_string(), charset)
+}
+
+#[test]
+fn ascii_is_a_single_latin1_run() {
+    assert_eq!(glyph::spans("Hello, world!"), vec![text("Hello, world!", Charset::Latin1)]);
+}
+
+#[test]
+fn accented_names_keep_their_accents() {
+    assert_eq!(glyph::spans("José Núñez"), vec![text("José Núñez", Charset::Latin1)]);
+}
+
+#[test]
+fn extended_letters_switch_fonts() {
+    assert_eq!(glyph::spans("Łukasz Dvořák"), vec![
+        text("Ł", Charset::Latin2),
+        text("ukasz Dvo", Charset::Latin1),
+        text("ř", Charset::Latin2),
+        text("ák", Charset::Latin1),
+    ]);
+    assert_eq!(glyph::spans("5€"), vec![text("5", Charset::Latin1), text("€", Charset::Latin9)]);
+}
+
+#[test]
+fn shortcodes_become_icons() {
+    assert_eq!(glyph::spans("I :heart: you"), vec![
+        text("I ", Charset::Latin1),
+        Span::Icon(Icon::Heart),
+        text(" you", Charset::Latin1),
+    ]);
+    assert_eq!(glyph::spans(":star::star:"), vec![Span::Icon(Icon::Star), Span::Icon(Icon::Star)]);
+}
+
+#[test]
+fn unknown_shortcodes_stay_text() {
+    assert_eq!(glyph::spans("at 10:30: :nope:"), vec![text("at 10:30: :nope:", Charset::Latin1)]);
+}
+
+#[test]
+fn emoji_become_icons() {
+    assert_eq!(glyph::spans("❤️👍🏽"), vec![Span::Icon(Icon::Heart), Span::Icon(Icon::ThumbsUp)]);
+}
+
+#[test]
+fn missing_glyphs_become_question_marks() {
+    assert_eq!(glyph::spans("🦀 Ω"), vec![text("? ?", Charset::Latin1)]);
+}
+
+#[test]
+fn lines_are_split() {
+    assert_eq!(glyph::spans("one\ntwo"), vec![
+        text("one", Charset::Latin1),
+        Span::Newline,
+        text("two", Charset::Latin1),
+    ]);
+}
+
+#[test]
+fn every_icon_has_a_name_and_fits() {
+    for icon in Icon::ALL {
+        assert_eq!(Icon::from_name(icon.name()), Some(icon));
+        assert!(icon.rows().iter().all(|row| row.len() == glyph::ICON_SIZE as usize), "{:?} is not square", icon);
+    }
+}
+
+#[test]
+fn icons_advance_the_cursor() {
+    let mut display = MockDisplay::<BinaryColor>::new();
+
+    let end = glyph::draw_text(&mut display, ":heart:", Point::zero(), None).unwrap();
+
+    assert_eq!(end, Point::new(glyph::ICON_SIZE as i32, 0));
+    let lit = Icon::Heart.rows().iter().map(|row| row.matches('#').count()).sum::<usize>();
+    assert_eq!(display.affected_area().size.height, 10);
+    assert_eq!(display.bounding_box().points().filter(|p| display.get_pixel(*p) == Some(BinaryColor::On)).count(), lit);
+}
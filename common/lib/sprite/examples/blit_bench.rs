@@ -0,0 +1,69 @@
+// Compares the per-pixel sprite rendering the client used to do on every frame against
+// blitting pre-decoded frames. That both produce the same display buffer is checked by
+// tests/blit.rs.
+//
+// cargo run --release --example blit_bench
+
+use std::time::Instant;
+
+use embedded_graphics::image::GetPixel;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use sprite::Sprite;
+
+const DISPLAY_WIDTH: usize = 128;
+const DISPLAY_HEIGHT: usize = 64;
+
+// Mirrors `Ssd1306::clear` followed by `Ssd1306::set_pixel` for every lit pixel of the frame.
+fn render_per_pixel(sprite: &Sprite, frame_index: usize, buffer: &mut [u8]) {
+    buffer.fill(0);
+
+    let frame_bounds = sprite.frame_bounds(frame_index);
+    for point in frame_bounds.points() {
+        let pixel = sprite.bmp.pixel(point).unwrap();
+
+        if pixel == BinaryColor::On {
+            let draw_point = point - frame_bounds.top_left;
+            let idx = (draw_point.y as usize / 8 * DISPLAY_WIDTH) + draw_point.x as usize;
+            let bit = draw_point.y % 8;
+            buffer[idx] = buffer[idx] & !(1 << bit) | (1 << bit);
+        }
+    }
+}
+
+fn bench(name: &str, sprite: &Sprite) {
+    let mut legacy = [0u8; DISPLAY_WIDTH * DISPLAY_HEIGHT / 8];
+    let mut blitted = [0u8; DISPLAY_WIDTH * DISPLAY_HEIGHT / 8];
+
+    let start = Instant::now();
+    let frames = sprite.frames();
+    let decode_time = start.elapsed();
+
+    let iterations = 20;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for frame_index in 0..sprite.frame_count {
+            render_per_pixel(sprite, frame_index, &mut legacy);
+        }
+    }
+    let per_pixel = start.elapsed() / (iterations * sprite.frame_count) as u32;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        for frame in &frames {
+            blitted.copy_from_slice(frame.as_bytes());
+        }
+    }
+    let blit = start.elapsed() / (iterations * sprite.frame_count) as u32;
+
+    println!("{:<12} frames: {:>3}  decode once: {:>10?}  per-pixel/frame: {:>10?}  blit/frame: {:>10?}",
+        name, sprite.frame_count, decode_time, per_pixel, blit);
+}
+
+fn main() {
+    bench("CartoonEyes", &Sprite::new(include_bytes!("../../../../client/media/eyes.bmp"), 128, 64, 10, 4, 40));
+    bench("Heart",       &Sprite::new(include_bytes!("../../../../client/media/heart.bmp"), 128, 64, 4, 7, 28));
+    bench("Unicorn",     &Sprite::new(include_bytes!("../../../../client/media/unicorn.bmp"), 128, 64, 4, 7, 28));
+}
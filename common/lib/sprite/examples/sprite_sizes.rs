@@ -0,0 +1,27 @@
+// Encodes the client's sprite sheets and reports how much flash each representation takes.
+// That they decode back to the same frames is checked by tests/codec.rs.
+//
+// cargo run --release --example sprite_sizes
+
+use sprite::codec::encode;
+use sprite::Sprite;
+
+fn report(name: &str, bmp_data: &[u8], sprite: &Sprite) {
+    let frames = sprite.frames();
+    let encoded = encode(&frames);
+
+    let packed: usize = frames.iter().map(|f| f.as_bytes().len()).sum();
+
+    println!("{:<12} bmp: {:>7} B  packed: {:>7} B  encoded: {:>6} B  ({:.1}% of bmp)",
+        name, bmp_data.len(), packed, encoded.len(), 100.0 * encoded.len() as f32 / bmp_data.len() as f32);
+}
+
+fn main() {
+    let eyes    = include_bytes!("../../../../client/media/eyes.bmp");
+    let heart   = include_bytes!("../../../../client/media/heart.bmp");
+    let unicorn = include_bytes!("../../../../client/media/unicorn.bmp");
+
+    report("CartoonEyes", eyes,    &Sprite::new(eyes, 128, 64, 10, 4, 40));
+    report("Heart",       heart,   &Sprite::new(heart, 128, 64, 4, 7, 28));
+    report("Unicorn",     unicorn, &Sprite::new(unicorn, 128, 64, 4, 7, 28));
+}
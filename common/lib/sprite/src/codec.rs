@@ -0,0 +1,218 @@
+//! Compact storage format for animations.
+//!
+//! Frames are stored in the packed page layout of [`Frame`], each one either as a key frame or
+//! as the XOR delta against the previous frame, whichever is smaller. The bytes of each frame are
+//! then run-length encoded.
+//!
+//! ```text
+//! "MBS1" | width: u16 | height: u16 | frame_count: u16 | frame*
+//! frame:   kind: u8 (0 = key, 1 = delta) | run*
+//! run:     0x00..=0x7f -> (n + 1) literal bytes follow
+//!          0x80..=0xff -> next byte repeated (n & 0x7f) + 1 times
+//! ```
+//!
+//! All integers are little endian. The decoder only ever holds one frame in memory.
+
+use std::fmt;
+
+use crate::Frame;
+
+const MAGIC: &[u8; 4] = b"MBS1";
+const HEADER_LEN: usize = 10;
+
+const KEY_FRAME: u8 = 0;
+const DELTA_FRAME: u8 = 1;
+
+const MAX_RUN: usize = 128;
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    BadMagic,
+    UnknownFrameKind(u8),
+    Truncated,
+    Overflow,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not an encoded sprite"),
+            DecodeError::UnknownFrameKind(k) => write!(f, "unknown frame kind {}", k),
+            DecodeError::Truncated => write!(f, "sprite data is truncated"),
+            DecodeError::Overflow => write!(f, "run overflows the frame"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Encode a sequence of equally sized frames.
+pub fn encode(frames: &[Frame]) -> Vec<u8> {
+    let (width, height) = frames.first().map(|f| (f.width(), f.height())).unwrap_or((0, 0));
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&(width as u16).to_le_bytes());
+    out.extend_from_slice(&(height as u16).to_le_bytes());
+    out.extend_from_slice(&(frames.len() as u16).to_le_bytes());
+
+    let mut previous: Option<&Frame> = None;
+    for frame in frames {
+        assert_eq!((frame.width(), frame.height()), (width, height), "frames must share the same size");
+
+        let key = rle_encode(frame.as_bytes());
+
+        let delta = previous.map(|p| {
+            let xor: Vec<u8> = frame.as_bytes().iter().zip(p.as_bytes()).map(|(a, b)| a ^ b).collect();
+            rle_encode(&xor)
+        });
+
+        match delta {
+            Some(delta) if delta.len() < key.len() => {
+                out.push(DELTA_FRAME);
+                out.extend_from_slice(&delta);
+            }
+            _ => {
+                out.push(KEY_FRAME);
+                out.extend_from_slice(&key);
+            }
+        }
+
+        previous = Some(frame);
+    }
+
+    out
+}
+
+fn rle_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+
+    let flush_literals = |literals: &mut Vec<u8>, out: &mut Vec<u8>| {
+        for chunk in literals.chunks(MAX_RUN) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+        literals.clear();
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let value = data[i];
+        let run = data[i..].iter().take(MAX_RUN).take_while(|&&b| b == value).count();
+
+        // A repeat costs two bytes, so anything shorter than three is cheaper as literals
+        if run >= 3 {
+            flush_literals(&mut literals, &mut out);
+            out.push(0x80 | (run - 1) as u8);
+            out.push(value);
+            i += run;
+        } else {
+            literals.push(value);
+            i += 1;
+        }
+    }
+    flush_literals(&mut literals, &mut out);
+
+    out
+}
+
+/// Streaming decoder over an encoded sprite, producing one frame at a time.
+pub struct SpriteDecoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    frame_index: usize,
+    frame_count: usize,
+    frame: Frame,
+}
+
+impl<'a> SpriteDecoder<'a> {
+
+    pub fn new(data: &'a [u8]) -> Result<Self, DecodeError> {
+        if data.len() < HEADER_LEN {
+            return Err(DecodeError::Truncated);
+        }
+        if &data[0..4] != MAGIC {
+            return Err(DecodeError::BadMagic);
+        }
+
+        let width = u16::from_le_bytes([data[4], data[5]]) as usize;
+        let height = u16::from_le_bytes([data[6], data[7]]) as usize;
+        let frame_count = u16::from_le_bytes([data[8], data[9]]) as usize;
+
+        Ok(SpriteDecoder {
+            data,
+            position: HEADER_LEN,
+            frame_index: 0,
+            frame_count,
+            frame: Frame::new(width, height),
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Start again from the first frame.
+    pub fn rewind(&mut self) {
+        self.position = HEADER_LEN;
+        self.frame_index = 0;
+        self.frame.clear_buffer();
+    }
+
+    /// Decode the next frame, or return `None` once every frame has been produced.
+    pub fn next_frame(&mut self) -> Result<Option<&Frame>, DecodeError> {
+        if self.frame_index >= self.frame_count {
+            return Ok(None);
+        }
+
+        let kind = self.read_byte()?;
+        let delta = match kind {
+            KEY_FRAME => false,
+            DELTA_FRAME => true,
+            k => return Err(DecodeError::UnknownFrameKind(k)),
+        };
+
+        let frame_len = self.frame.as_bytes().len();
+        let mut offset = 0;
+
+        while offset < frame_len {
+            let control = self.read_byte()?;
+            let count = (control & 0x7f) as usize + 1;
+
+            if offset + count > frame_len {
+                return Err(DecodeError::Overflow);
+            }
+
+            if control & 0x80 != 0 {
+                let value = self.read_byte()?;
+                let target = &mut self.frame.as_bytes_mut()[offset..offset + count];
+                if delta {
+                    target.iter_mut().for_each(|b| *b ^= value);
+                } else {
+                    target.fill(value);
+                }
+            } else {
+                let source = self.data.get(self.position..self.position + count).ok_or(DecodeError::Truncated)?;
+                let target = &mut self.frame.as_bytes_mut()[offset..offset + count];
+                if delta {
+                    target.iter_mut().zip(source).for_each(|(b, s)| *b ^= s);
+                } else {
+                    target.copy_from_slice(source);
+                }
+                self.position += count;
+            }
+
+            offset += count;
+        }
+
+        self.frame_index += 1;
+        Ok(Some(&self.frame))
+    }
+
+    fn read_byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.data.get(self.position).ok_or(DecodeError::Truncated)?;
+        self.position += 1;
+        Ok(byte)
+    }
+}
@@ -0,0 +1,176 @@
+pub mod codec;
+
+use std::convert::Infallible;
+use std::io;
+use std::path::Path;
+
+use tinybmp::Bmp;
+
+use embedded_graphics::image::GetPixel;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::Rectangle;
+
+/// A single animation frame, pre-decoded into the SSD1306 page layout.
+///
+/// The buffer is organised as `height / 8` pages of `width` bytes. Each byte holds a vertical
+/// strip of 8 pixels with the least significant bit at the top, which is exactly what the
+/// display expects on the wire, so a frame can be sent with a single `draw` call.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    buffer: Vec<u8>,
+}
+
+impl Frame {
+
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            width,
+            height,
+            buffer: vec![0u8; width * height.div_ceil(8)],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+
+    pub fn clear_buffer(&mut self) {
+        self.buffer.fill(0);
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x < self.width && y < self.height {
+            let byte = &mut self.buffer[(y / 8) * self.width + x];
+            let bit = y % 8;
+            *byte = *byte & !(1 << bit) | ((on as u8) << bit);
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.buffer[(y / 8) * self.width + x] & (1 << (y % 8)) != 0
+    }
+}
+
+impl OriginDimensions for Frame {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+
+/// Drawing onto a frame lets overlays be composed over an animation before it is blitted.
+impl DrawTarget for Frame {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x >= 0 && point.y >= 0 {
+                self.set_pixel(point.x as usize, point.y as usize, color.is_on());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A sprite sheet of equally sized frames laid out left to right, top to bottom.
+pub struct Sprite<'a> {
+    pub bmp: Bmp<'a, BinaryColor>,
+    pub rows: usize,
+    pub cols: usize,
+    pub width: usize,
+    pub height: usize,
+    pub frame_count: usize,
+}
+
+impl<'a> Sprite<'a> {
+
+    pub fn new(bmp_data: &'a[u8], width: usize, height: usize, cols: usize, rows: usize, frame_count: usize) -> Self {
+        Sprite {
+            bmp: Bmp::<BinaryColor>::from_slice(bmp_data).unwrap(),
+            rows,
+            cols,
+            width,
+            height,
+            frame_count,
+        }
+    }
+
+    /// Bounds of the frame at `frame_index` within the sprite sheet.
+    pub fn frame_bounds(&self, frame_index: usize) -> Rectangle {
+        let row = frame_index / self.cols;
+        let col = frame_index % self.cols;
+
+        let frame_origin = Point::new((col*self.width) as i32, (row*self.height) as i32);
+        Rectangle::new(frame_origin, Size::new(self.width as u32, self.height as u32))
+    }
+
+    /// Decode a single frame of the sheet into the packed page layout.
+    pub fn decode_frame(&self, frame_index: usize) -> Frame {
+        let frame_bounds = self.frame_bounds(frame_index);
+        let mut frame = Frame::new(self.width, self.height);
+
+        for point in frame_bounds.points() {
+            if self.bmp.pixel(point) == Some(BinaryColor::On) {
+                let draw_point = point - frame_bounds.top_left;
+                frame.set_pixel(draw_point.x as usize, draw_point.y as usize, true);
+            }
+        }
+
+        frame
+    }
+
+    /// Decode every frame of the sheet. This is relatively slow and is meant to be done once
+    /// when an animation is selected, not on every frame.
+    pub fn frames(&self) -> Vec<Frame> {
+        (0..self.frame_count).map(|i| self.decode_frame(i)).collect()
+    }
+}
+
+/// One of the sprite sheets in `client/media` and how its frames are laid out.
+pub struct Sheet {
+    /// Name the animation goes by in directives.
+    pub animation: &'static str,
+    /// Stem of the sheet's file, and of the encoded file built from it.
+    pub file: &'static str,
+    pub width: usize,
+    pub height: usize,
+    pub cols: usize,
+    pub rows: usize,
+    pub frame_count: usize,
+}
+
+/// The sheets every worker carries. The client and the simulator both encode them at build time.
+pub const SHEETS: [Sheet; 3] = [
+    Sheet { animation: "CartoonEyes", file: "eyes",    width: 128, height: 64, cols: 10, rows: 4, frame_count: 40 },
+    Sheet { animation: "Heart",       file: "heart",   width: 128, height: 64, cols: 4,  rows: 7, frame_count: 28 },
+    Sheet { animation: "Unicorn",     file: "unicorn", width: 128, height: 64, cols: 4,  rows: 7, frame_count: 28 },
+];
+
+/// Encode every one of [`SHEETS`] in `media_dir` with the [`codec`], to `<file>.mbs` in
+/// `out_dir`. Meant for build scripts.
+pub fn encode_sheets(media_dir: &Path, out_dir: &Path) -> io::Result<()> {
+    for sheet in SHEETS {
+        let bmp_data = std::fs::read(media_dir.join(format!("{}.bmp", sheet.file)))?;
+        let sprite = Sprite::new(&bmp_data, sheet.width, sheet.height, sheet.cols, sheet.rows, sheet.frame_count);
+        std::fs::write(out_dir.join(format!("{}.mbs", sheet.file)), codec::encode(&sprite.frames()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// Blitting pre-decoded frames has to light exactly the pixels the per-pixel rendering the client
+// used to do on every frame did.
+
+use embedded_graphics::image::GetPixel;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+use sprite::Sprite;
+
+const DISPLAY_WIDTH: usize = 128;
+const DISPLAY_HEIGHT: usize = 64;
+
+// Mirrors `Ssd1306::clear` followed by `Ssd1306::set_pixel` for every lit pixel of the frame.
+fn render_per_pixel(sprite: &Sprite, frame_index: usize) -> [u8; DISPLAY_WIDTH * DISPLAY_HEIGHT / 8] {
+    let mut buffer = [0u8; DISPLAY_WIDTH * DISPLAY_HEIGHT / 8];
+
+    let frame_bounds = sprite.frame_bounds(frame_index);
+    for point in frame_bounds.points() {
+        if sprite.bmp.pixel(point).unwrap() == BinaryColor::On {
+            let draw_point = point - frame_bounds.top_left;
+            let idx = (draw_point.y as usize / 8 * DISPLAY_WIDTH) + draw_point.x as usize;
+            buffer[idx] |= 1 << (draw_point.y % 8);
+        }
+    }
+    buffer
+}
+
+fn assert_blits_match(name: &str, sprite: &Sprite) {
+    let frames = sprite.frames();
+    assert_eq!(frames.len(), sprite.frame_count);
+
+    for (frame_index, frame) in frames.iter().enumerate() {
+        assert_eq!(frame.as_bytes(), render_per_pixel(sprite, frame_index), "{} frame {} differs", name, frame_index);
+    }
+}
+
+#[test]
+fn cartoon_eyes_blit_like_per_pixel_rendering() {
+    assert_blits_match("CartoonEyes", &Sprite::new(include_bytes!("../../../../client/media/eyes.bmp"), 128, 64, 10, 4, 40));
+}
+
+#[test]
+fn heart_blits_like_per_pixel_rendering() {
+    assert_blits_match("Heart", &Sprite::new(include_bytes!("../../../../client/media/heart.bmp"), 128, 64, 4, 7, 28));
+}
+
+#[test]
+fn unicorn_blits_like_per_pixel_rendering() {
+    assert_blits_match("Unicorn", &Sprite::new(include_bytes!("../../../../client/media/unicorn.bmp"), 128, 64, 4, 7, 28));
+}
+
+#[test]
+fn decoded_frames_are_what_decode_frame_gives() {
+    let sprite = Sprite::new(include_bytes!("../../../../client/media/heart.bmp"), 128, 64, 4, 7, 28);
+    for (frame_index, frame) in sprite.frames().iter().enumerate() {
+        assert_eq!(frame.as_bytes(), sprite.decode_frame(frame_index).as_bytes());
+    }
+}
@@ -0,0 +1,96 @@
+// Every bundled animation survives encoding, and broken data is turned down rather than decoded.
+
+use sprite::codec::{encode, DecodeError, SpriteDecoder};
+use sprite::{Frame, Sprite};
+
+fn assert_round_trip(name: &str, sprite: &Sprite) {
+    let frames = sprite.frames();
+    let encoded = encode(&frames);
+
+    let mut decoder = SpriteDecoder::new(&encoded).unwrap();
+    assert_eq!(decoder.frame_count(), frames.len());
+
+    // Twice, the client loops animations
+    for _ in 0..2 {
+        let mut frame_index = 0;
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            assert_eq!(frame, &frames[frame_index], "{} frame {} differs after round trip", name, frame_index);
+            frame_index += 1;
+        }
+        assert_eq!(frame_index, frames.len());
+        decoder.rewind();
+    }
+}
+
+// Header of an 8x8 sprite, one byte per column
+fn header(frame_count: u16) -> Vec<u8> {
+    let mut data = b"MBS1".to_vec();
+    data.extend_from_slice(&8u16.to_le_bytes());
+    data.extend_from_slice(&8u16.to_le_bytes());
+    data.extend_from_slice(&frame_count.to_le_bytes());
+    data
+}
+
+fn first_frame(data: &[u8]) -> Result<Option<Frame>, DecodeError> {
+    SpriteDecoder::new(data)?.next_frame().map(|f| f.cloned())
+}
+
+#[test]
+fn cartoon_eyes_round_trip() {
+    assert_round_trip("CartoonEyes", &Sprite::new(include_bytes!("../../../../client/media/eyes.bmp"), 128, 64, 10, 4, 40));
+}
+
+#[test]
+fn heart_round_trips() {
+    assert_round_trip("Heart", &Sprite::new(include_bytes!("../../../../client/media/heart.bmp"), 128, 64, 4, 7, 28));
+}
+
+#[test]
+fn unicorn_round_trips() {
+    assert_round_trip("Unicorn", &Sprite::new(include_bytes!("../../../../client/media/unicorn.bmp"), 128, 64, 4, 7, 28));
+}
+
+#[test]
+fn runs_decode_into_key_and_delta_frames() {
+    let mut data = header(2);
+    data.extend_from_slice(&[0, 0x87, 0xff]);
+    data.extend_from_slice(&[1, 0x01, 0x0f, 0xf0, 0x85, 0x00]);
+
+    let mut decoder = SpriteDecoder::new(&data).unwrap();
+    assert_eq!(decoder.next_frame().unwrap().unwrap().as_bytes(), [0xff; 8]);
+    assert_eq!(decoder.next_frame().unwrap().unwrap().as_bytes(), [0xf0, 0x0f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(decoder.next_frame(), Ok(None));
+}
+
+#[test]
+fn other_data_is_not_a_sprite() {
+    let mut data = header(1);
+    data[3] = b'2';
+    assert_eq!(SpriteDecoder::new(&data).err(), Some(DecodeError::BadMagic));
+}
+
+#[test]
+fn unknown_frame_kinds_are_refused() {
+    let mut data = header(1);
+    data.extend_from_slice(&[7, 0x87, 0xff]);
+    assert_eq!(first_frame(&data), Err(DecodeError::UnknownFrameKind(7)));
+}
+
+#[test]
+fn truncated_data_is_refused() {
+    assert_eq!(SpriteDecoder::new(&header(1)[..6]).err(), Some(DecodeError::Truncated));
+
+    let mut data = header(1);
+    data.extend_from_slice(&[0, 0x07, 1, 2, 3]);
+    assert_eq!(first_frame(&data), Err(DecodeError::Truncated));
+
+    // Frames the header promises but that aren't there
+    assert_eq!(first_frame(&header(1)), Err(DecodeError::Truncated));
+}
+
+#[test]
+fn runs_past_the_frame_are_refused() {
+    let mut data = header(1);
+    data.extend_from_slice(&[0, 0x8f, 0xff]);
+    assert_eq!(first_frame(&data), Err(DecodeError::Overflow));
+}
@@ -0,0 +1,121 @@
+//! What a worker reports about itself.
+//!
+//! A report is a single line of `key=value` pairs, values without spaces:
+//!
+//! ```text
+//! TELEMETRY rssi=-61 uptime=3600 reset=poweron heap=118240 firmware=0.1.0 display=ok
+//! ```
+//!
+//! Workers send one right after `ACCEPTED` on the registration connection, and then every
+//! [`INTERVAL`] as the answer to a directive, once they have read it to the end. Every field is
+//! optional and unknown keys are skipped, so workers and servers of different ages get along.
+//!
+//! Workers also forward what they log, a [`LogLine`] each after the report, if any:
+//!
+//! ```text
+//! LOG warn Display not found, running headless
+//! ```
+
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const TELEMETRY: &str = "TELEMETRY";
+pub const LOG: &str = "LOG";
+
+/// How often a worker answers a directive with a report.
+pub const INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Telemetry {
+    /// Signal strength of the access point in dBm.
+    pub rssi: Option<i32>,
+    /// Seconds since the worker started.
+    pub uptime: Option<u64>,
+    /// Why the worker last started, such as `poweron` or `panic`.
+    pub reset_reason: Option<String>,
+    /// Free heap in bytes.
+    pub free_heap: Option<u32>,
+    pub firmware: Option<String>,
+    /// Whether the display is working, `ok` when it is.
+    pub display: Option<String>,
+}
+
+impl Telemetry {
+
+    /// The report as a line, fields that aren't known are left out.
+    pub fn line(&self) -> String {
+        let mut line = TELEMETRY.to_string();
+
+        let fields = [
+            ("rssi", self.rssi.map(|v| v.to_string())),
+            ("uptime", self.uptime.map(|v| v.to_string())),
+            ("reset", self.reset_reason.as_deref().map(value)),
+            ("heap", self.free_heap.map(|v| v.to_string())),
+            ("firmware", self.firmware.as_deref().map(value)),
+            ("display", self.display.as_deref().map(value)),
+        ];
+        for (key, v) in fields {
+            if let Some(v) = v {
+                line.push_str(&format!(" {}={}", key, v));
+            }
+        }
+
+        line.push('\n');
+        line
+    }
+
+    /// Parse a `TELEMETRY` line, `None` if it is something else.
+    pub fn parse(line: &str) -> Option<Telemetry> {
+        let mut fields = line.split_ascii_whitespace();
+        if fields.next()? != TELEMETRY {
+            return None;
+        }
+
+        let mut telemetry = Telemetry::default();
+        for (key, v) in fields.filter_map(|f| f.split_once('=')) {
+            match key {
+                "rssi"     => telemetry.rssi = i32::from_str(v).ok(),
+                "uptime"   => telemetry.uptime = u64::from_str(v).ok(),
+                "reset"    => telemetry.reset_reason = Some(v.to_string()),
+                "heap"     => telemetry.free_heap = u32::from_str(v).ok(),
+                "firmware" => telemetry.firmware = Some(v.to_string()),
+                "display"  => telemetry.display = Some(v.to_string()),
+                _ => (),
+            }
+        }
+
+        Some(telemetry)
+    }
+}
+
+/// A line a worker logged, forwarded to the server.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LogLine {
+    /// `error`, `warn`, `info`, `debug` or `trace`.
+    pub level: String,
+    pub message: String,
+}
+
+impl LogLine {
+
+    /// The forwarded line, a message spanning several lines is joined into one.
+    pub fn line(&self) -> String {
+        format!("{} {} {}\n", LOG, value(&self.level), self.message.lines().collect::<Vec<_>>().join(" "))
+    }
+
+    /// Parse a `LOG` line, `None` if it is something else.
+    pub fn parse(line: &str) -> Option<LogLine> {
+        let rest = line.strip_prefix(LOG)?.strip_prefix(' ')?;
+        let (level, message) = rest.split_once(' ').unwrap_or((rest, ""));
+        if level.is_empty() {
+            return None;
+        }
+
+        Some(LogLine { level: level.to_ascii_lowercase(), message: message.trim_end().to_string() })
+    }
+}
+
+// Text values can't hold the separators
+fn value(v: &str) -> String {
+    v.replace(|c: char| c.is_whitespace() || c == '=', "_")
+}
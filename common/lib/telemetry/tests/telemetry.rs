@@ -0,0 +1,70 @@
+// Reports written by workers and read back by the server.
+
+use telemetry::{LogLine, Telemetry};
+
+fn full() -> Telemetry {
+    Telemetry {
+        rssi: Some(-61),
+        uptime: Some(3600),
+        reset_reason: Some("poweron".to_string()),
+        free_heap: Some(118240),
+        firmware: Some("0.1.0".to_string()),
+        display: Some("ok".to_string()),
+    }
+}
+
+#[test]
+fn report_is_one_line() {
+    assert_eq!(full().line(), "TELEMETRY rssi=-61 uptime=3600 reset=poweron heap=118240 firmware=0.1.0 display=ok\n");
+}
+
+#[test]
+fn report_round_trips() {
+    assert_eq!(Telemetry::parse(&full().line()), Some(full()));
+}
+
+#[test]
+fn missing_fields_are_left_out() {
+    let telemetry = Telemetry { uptime: Some(5), ..Telemetry::default() };
+
+    assert_eq!(telemetry.line(), "TELEMETRY uptime=5\n");
+    assert_eq!(Telemetry::parse("TELEMETRY\n"), Some(Telemetry::default()));
+}
+
+#[test]
+fn values_cannot_break_the_line() {
+    let telemetry = Telemetry { firmware: Some("1.0 beta=2".to_string()), ..Telemetry::default() };
+
+    assert_eq!(Telemetry::parse(&telemetry.line()).unwrap().firmware.as_deref(), Some("1.0_beta_2"));
+}
+
+#[test]
+fn unknown_and_malformed_fields_are_skipped() {
+    let telemetry = Telemetry::parse("TELEMETRY rssi=loud battery=80 uptime=12 junk").unwrap();
+
+    assert_eq!(telemetry.rssi, None);
+    assert_eq!(telemetry.uptime, Some(12));
+}
+
+#[test]
+fn other_lines_are_not_reports() {
+    assert_eq!(Telemetry::parse("ACCEPTED\n"), None);
+    assert_eq!(Telemetry::parse(""), None);
+}
+
+#[test]
+fn log_lines_round_trip() {
+    let log = LogLine { level: "warn".to_string(), message: "Display not found, running headless".to_string() };
+
+    assert_eq!(log.line(), "LOG warn Display not found, running headless\n");
+    assert_eq!(LogLine::parse(&log.line()), Some(log));
+    assert_eq!(LogLine::parse("TELEMETRY uptime=5\n"), None);
+    assert_eq!(Telemetry::parse("LOG info Started\n"), None);
+}
+
+#[test]
+fn log_messages_stay_on_one_line() {
+    let log = LogLine { level: "error".to_string(), message: "Failed:\nno route".to_string() };
+
+    assert_eq!(log.line(), "LOG error Failed: no route\n");
+}
@@ -1,28 +1,64 @@
-use anyhow::{bail, Result};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
 use esp_idf_svc::{
-    eventloop::EspSystemEventLoop,
+    eventloop::{EspSubscription, EspSystemEventLoop, System},
     hal::peripheral,
-    wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi},
+    wifi::{AccessPointConfiguration, AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, WifiDeviceId, WifiEvent},
 };
 use log::info;
 
-pub fn wifi(
-    ssid: &str,
-    pass: &str,
+pub use backoff::Backoff;
+
+/// A network to join. [`connect`] tries them in the order given.
+#[derive(Clone, Debug)]
+pub struct Network {
+    pub ssid: String,
+    pub psk: String,
+}
+
+/// Progress reported to the callback given to [`connect`].
+#[derive(Clone, PartialEq, Debug)]
+pub enum Status {
+    Connecting(String),
+    Connected(String),
+    Disconnected,
+    Retrying { attempt: u32, delay: Duration },
+}
+
+/// A station connection that rejoins on its own when the access point goes away.
+pub struct ResilientWifi {
+    wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    _subscription: EspSubscription<'static, System>,
+}
+
+impl ResilientWifi {
+    pub fn get_mac(&self, device: WifiDeviceId) -> Result<[u8; 6]> {
+        Ok(self.wifi.lock().unwrap().wifi().get_mac(device)?)
+    }
+}
+
+type StatusCallback = Arc<dyn Fn(&Status) + Send + Sync>;
+
+/// Join the first reachable network of `networks`, retrying with `backoff` for up to
+/// `initial_attempts` rounds. Once connected, a background thread rejoins after every disconnect,
+/// for as long as the returned handle lives. Every step is reported to `on_status`.
+pub fn connect(
+    networks: Vec<Network>,
+    initial_attempts: u32,
+    backoff: Backoff,
     modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
-) -> Result<Box<EspWifi<'static>>> {
-    let mut auth_method = AuthMethod::WPA2Personal;
-    if ssid.is_empty() {
+    on_status: impl Fn(&Status) + Send + Sync + 'static,
+) -> Result<ResilientWifi> {
+    if networks.is_empty() {
         bail!("Missing WiFi name")
     }
-    if pass.is_empty() {
-        auth_method = AuthMethod::None;
-        info!("Wifi password is empty");
-    }
-    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let on_status: StatusCallback = Arc::new(on_status);
 
-    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+    let esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    let mut wifi = BlockingWifi::wrap(esp_wifi, sysloop.clone())?;
 
     wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
 
@@ -30,39 +66,109 @@ pub fn wifi(
 
     wifi.start()?;
 
+    let mut attempt = 0;
+    while let Err(e) = join_any(&mut wifi, &networks, &*on_status) {
+        if attempt + 1 >= initial_attempts {
+            return Err(e);
+        }
+        let delay = backoff.delay(attempt);
+        attempt += 1;
+        on_status(&Status::Retrying { attempt, delay });
+        std::thread::sleep(delay);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let subscription = sysloop.subscribe::<WifiEvent, _>(move |event| {
+        if let WifiEvent::StaDisconnected(..) = event {
+            let _ = sender.send(());
+        }
+    })?;
+
+    let wifi = Arc::new(Mutex::new(wifi));
+
+    std::thread::spawn({
+        let wifi = wifi.clone();
+        move || supervise(wifi, networks, backoff, receiver, on_status)
+    });
+
+    Ok(ResilientWifi { wifi, _subscription: subscription })
+}
+
+// Rejoin whenever a disconnect is reported. Ends when the subscription is dropped.
+fn supervise(
+    wifi: Arc<Mutex<BlockingWifi<EspWifi<'static>>>>,
+    networks: Vec<Network>,
+    backoff: Backoff,
+    disconnects: mpsc::Receiver<()>,
+    on_status: StatusCallback,
+) {
+    while disconnects.recv().is_ok() {
+        if wifi.lock().unwrap().is_connected().unwrap_or(false) {
+            continue;
+        }
+
+        info!("Wifi disconnected");
+        on_status(&Status::Disconnected);
+
+        let mut attempt = 0;
+        loop {
+            // The guard goes at the end of the statement, others get the handle while we sleep
+            let joined = join_any(&mut wifi.lock().unwrap(), &networks, &*on_status);
+            let Err(e) = joined else { break };
+
+            let delay = backoff.delay(attempt);
+            attempt += 1;
+            info!("Reconnecting failed: {}, retrying in {:?}", e, delay);
+            on_status(&Status::Retrying { attempt, delay });
+            std::thread::sleep(delay);
+        }
+
+        // Failed attempts raise disconnect events of their own
+        while disconnects.try_recv().is_ok() {}
+    }
+}
+
+// Try every network once. Networks seen by the scan go first, the others may be hidden.
+fn join_any(wifi: &mut BlockingWifi<EspWifi<'static>>, networks: &[Network], on_status: &dyn Fn(&Status)) -> Result<()> {
     info!("Scanning...");
 
     let ap_infos = wifi.scan()?;
 
-    let ours = ap_infos.into_iter().find(|a| a.ssid == ssid);
-
-    let channel = if let Some(ours) = ours {
-        info!(
-            "Found configured access point {} on channel {}",
-            ssid, ours.channel
-        );
-        Some(ours.channel)
-    } else {
-        info!(
-            "Configured access point {} not found during scanning, will go with unknown channel",
-            ssid
-        );
-        None
-    };
+    let (visible, hidden): (Vec<&Network>, Vec<&Network>) = networks.iter()
+        .partition(|n| ap_infos.iter().any(|a| a.ssid == n.ssid.as_str()));
+
+    for network in visible.into_iter().chain(hidden) {
+        let channel = ap_infos.iter().find(|a| a.ssid == network.ssid.as_str()).map(|a| a.channel);
+
+        on_status(&Status::Connecting(network.ssid.clone()));
+
+        match join(wifi, network, channel) {
+            Ok(()) => {
+                on_status(&Status::Connected(network.ssid.clone()));
+                return Ok(());
+            }
+            Err(e) => {
+                info!("Unable to join {}: {}", network.ssid, e);
+                let _ = wifi.disconnect();
+            }
+        }
+    }
+
+    bail!("None of the configured networks could be joined")
+}
+
+fn join(wifi: &mut BlockingWifi<EspWifi<'static>>, network: &Network, channel: Option<u8>) -> Result<()> {
+    let auth_method = if network.psk.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal };
 
     wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: ssid
-            .try_into()
-            .expect("Could not parse the given SSID into WiFi config"),
-        password: pass
-            .try_into()
-            .expect("Could not parse the given password into WiFi config"),
+        ssid: network.ssid.as_str().try_into().map_err(|_| anyhow!("Invalid WiFi name {}", network.ssid))?,
+        password: network.psk.as_str().try_into().map_err(|_| anyhow!("Invalid WiFi password"))?,
         channel,
         auth_method,
         ..Default::default()
     }))?;
 
-    info!("Connecting wifi...");
+    info!("Connecting to {} on channel {:?}...", network.ssid, channel);
 
     wifi.connect()?;
 
@@ -74,5 +180,35 @@ pub fn wifi(
 
     info!("Wifi DHCP info: {:?}", ip_info);
 
+    Ok(())
+}
+
+pub fn access_point(
+    ssid: &str,
+    modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
+    sysloop: EspSystemEventLoop,
+) -> Result<Box<EspWifi<'static>>> {
+    let mut esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+
+    let mut wifi = BlockingWifi::wrap(&mut esp_wifi, sysloop)?;
+
+    wifi.set_configuration(&Configuration::AccessPoint(AccessPointConfiguration {
+        ssid: ssid
+            .try_into()
+            .expect("Could not parse the given SSID into WiFi config"),
+        auth_method: AuthMethod::None,
+        ..Default::default()
+    }))?;
+
+    info!("Starting access point {}...", ssid);
+
+    wifi.start()?;
+
+    wifi.wait_netif_up()?;
+
+    let ip_info = wifi.wifi().ap_netif().get_ip_info()?;
+
+    info!("Access point IP info: {:?}", ip_info);
+
     Ok(Box::new(esp_wifi))
 }
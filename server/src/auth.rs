@@ -0,0 +1,147 @@
+//! Who may use the portal and the API.
+//!
+//! People log in with a name and password over HTTP Basic authentication, which browsers prompt
+//! for on their own. Scripts send `Authorization: Bearer <token>` instead. Each user and token has
+//! a role, and every route requires one:
+//!
+//! - viewer: the portal, read only
+//! - operator: sending messages, timers, animations and scenes
+//! - admin: managing the worker registry
+//!
+//! Without any users or tokens configured access is left open, as it was before roles existed.
+//!
+//! Handlers find the caller's [`Role`] and, for the record, who they are as a [`Caller`] in the
+//! request extensions.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use base64::Engine;
+use serde::Deserialize;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+/// Who sent a request, as far as the server can tell: a user name, "token" or "anyone" while
+/// access is open, and the address it came from.
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub name: String,
+    pub address: Option<IpAddr>,
+}
+
+impl fmt::Display for Caller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{}@{}", self.name, address),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Account {
+    password: String,
+    role: Role,
+}
+
+#[derive(Clone, Default)]
+pub struct AccessControl {
+    users: HashMap<String, Account>,
+    tokens: HashMap<String, Role>,
+}
+
+// Leave the secrets out of logged configurations
+impl fmt::Debug for AccessControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AccessControl")
+            .field("users", &self.users.iter().map(|(name, a)| (name, a.role)).collect::<Vec<_>>())
+            .field("tokens", &self.tokens.len())
+            .finish()
+    }
+}
+
+impl AccessControl {
+
+    pub fn add_user(&mut self, name: &str, password: &str, role: Role) {
+        self.users.insert(name.to_string(), Account { password: password.to_string(), role });
+    }
+
+    pub fn add_token(&mut self, token: &str, role: Role) {
+        self.tokens.insert(token.to_string(), role);
+    }
+
+    /// Whether no one has been configured, leaving everything open.
+    pub fn is_open(&self) -> bool {
+        self.users.is_empty() && self.tokens.is_empty()
+    }
+
+    /// The role of the credentials in `headers`, `None` if there are none or they are wrong.
+    pub fn role_for(&self, headers: &HeaderMap) -> Option<Role> {
+        self.identify(headers).map(|(_, role)| role)
+    }
+
+    // The name to record for the credentials in `headers`, along with their role
+    fn identify(&self, headers: &HeaderMap) -> Option<(String, Role)> {
+        if self.is_open() {
+            return Some(("anyone".to_string(), Role::Admin));
+        }
+
+        let authorization = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, credentials) = authorization.split_once(' ')?;
+
+        match scheme {
+            "Basic" => {
+                let decoded = base64::engine::general_purpose::STANDARD.decode(credentials.trim()).ok()?;
+                let decoded = String::from_utf8(decoded).ok()?;
+                let (name, password) = decoded.split_once(':')?;
+                let account = self.users.get(name)?;
+                constant_time_eq(account.password.as_bytes(), password.as_bytes()).then(|| (name.to_string(), account.role))
+            }
+            "Bearer" => {
+                let token = credentials.trim();
+                self.tokens.iter()
+                    .find(|(t, _)| constant_time_eq(t.as_bytes(), token.as_bytes()))
+                    .map(|(_, role)| ("token".to_string(), *role))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Middleware letting requests through only with at least the `required` role. The caller's
+/// role and [`Caller`] are added to the request extensions for handlers that need them.
+pub async fn authorize(State((access, required)): State<(Arc<AccessControl>, Role)>, mut request: Request, next: Next) -> Response {
+    match access.identify(request.headers()) {
+        Some((name, role)) if role >= required => {
+            let address = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+            request.extensions_mut().insert(role);
+            request.extensions_mut().insert(Caller { name, address });
+            next.run(request).await
+        }
+        Some((_, role)) => {
+            tracing::warn!(method = %request.method(), uri = %request.uri(), ?role, "Refused");
+            StatusCode::FORBIDDEN.into_response()
+        }
+        None => {
+            (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Basic realm=\"MicroBroadcast\"")]).into_response()
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! What may be shown on a worker's screen.
+//!
+//! Text from the portal and the API goes through [`ContentPolicy::sanitize`] before it becomes a
+//! directive. Workers draw Latin-1 and most of Latin-2 and a set of emoji as icons, see
+//! [`render::glyph`]. Other characters are transliterated: letters lose their accents,
+//! typographic punctuation becomes plain ASCII and the remaining emoji turn into text smileys.
+//! Words on the blocklist are masked, and the result has to fit the limit.
+
+use std::fmt;
+
+use render::glyph::{Charset, Icon};
+
+#[derive(Clone, Debug)]
+pub struct ContentPolicy {
+    /// Longest text accepted, in characters after transliteration.
+    pub max_len: usize,
+    /// Words masked wherever they appear as a whole word, ignoring case and accents.
+    pub blocklist: Vec<String>,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        // Matches the portal's textarea
+        ContentPolicy { max_len: 80, blocklist: Vec::new() }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Rejection {
+    Empty,
+    TooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::Empty => write!(f, "nothing left to show"),
+            Rejection::TooLong { len, max } => write!(f, "{} characters, at most {} fit", len, max),
+        }
+    }
+}
+
+impl ContentPolicy {
+
+    /// The text as it should be sent to a worker.
+    pub fn sanitize(&self, text: &str) -> Result<String, Rejection> {
+        let text = transliterate(text);
+        let text = self.mask(&text);
+        let text = text.trim_end().to_string();
+
+        let len = text.chars().count();
+        if text.trim().is_empty() {
+            Err(Rejection::Empty)
+        } else if len > self.max_len {
+            Err(Rejection::TooLong { len, max: self.max_len })
+        } else {
+            Ok(text)
+        }
+    }
+
+    // Replace blocked words with as many asterisks
+    fn mask(&self, text: &str) -> String {
+        let mut masked = String::with_capacity(text.len());
+        let mut word = String::new();
+
+        for c in text.chars().chain(std::iter::once('\0')) {
+            if c.is_alphanumeric() || c == '\'' {
+                word.push(c);
+                continue;
+            }
+
+            if !word.is_empty() && self.blocklist.iter().any(|b| fold(b).eq_ignore_ascii_case(&fold(&word))) {
+                masked.push_str(&"*".repeat(word.chars().count()));
+            } else {
+                masked.push_str(&word);
+            }
+            word.clear();
+
+            if c != '\0' {
+                masked.push(c);
+            }
+        }
+
+        masked
+    }
+}
+
+/// Rewrite `text` into characters a worker can draw and newlines.
+pub fn transliterate(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                // \r\n and lone \r both end a line
+                if chars.peek() != Some(&'\n') {
+                    out.push('\n');
+                }
+            }
+            '\n' => out.push('\n'),
+            '\t' => out.push(' '),
+            // Invisible joiners and emoji presentation selectors
+            '\u{200B}'..='\u{200D}' | '\u{FE00}'..='\u{FE0F}' | '\u{1F3FB}'..='\u{1F3FF}' => (),
+            _ if Charset::for_char(c).is_some() || Icon::from_char(c).is_some() => out.push(c),
+            _ => out.push_str(replacement(c)),
+        }
+    }
+
+    out
+}
+
+// Plain ASCII spelling of a word, for comparing against the blocklist
+fn fold(word: &str) -> String {
+    word.chars()
+        .map(|c| if c.is_ascii() { c.to_string() } else { replacement(c).to_string() })
+        .collect()
+}
+
+// Letters are listed even where workers have a glyph, they are still folded for the blocklist
+fn replacement(c: char) -> &'static str {
+    match c {
+        'À'..='Å' => "A",
+        'à'..='å' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Č' | 'Ć' => "C",
+        'ç' | 'č' | 'ć' => "c",
+        'È'..='Ë' | 'Ē' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ę' | 'ě' => "e",
+        'Ì'..='Ï' | 'Ī' | 'İ' => "I",
+        'ì'..='ï' | 'ī' | 'ı' => "i",
+        'Ð' => "D",
+        'ð' => "d",
+        'Ñ' | 'Ń' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ł' => "L",
+        'ł' => "l",
+        'Ř' => "R",
+        'ř' => "r",
+        'Ś' | 'Š' => "S",
+        'ś' | 'š' => "s",
+        'ß' => "ss",
+        'Þ' => "Th",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ů' | 'Ű' => "U",
+        'ù'..='ü' | 'ů' | 'ű' => "u",
+        'Ý' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+
+        '\u{2002}'..='\u{200A}' => " ",
+        '‘' | '’' | '‚' | '′' => "'",
+        '“' | '”' | '„' | '″' => "\"",
+        '‐' | '‑' | '‒' | '–' | '—' | '−' => "-",
+        '…' => "...",
+        '•' => "*",
+        '™' => "TM",
+
+        // Emoji without an icon
+        '💔' => "</3",
+        '😛' | '😜' | '😝' => ":P",
+        '😮' | '😯' | '😲' => ":O",
+        '😐' | '😑' => ":|",
+        '😎' => "B)",
+        '👎' => "-1",
+        '👋' => "o/",
+        '☆' => "*",
+        '🎉' | '🥳' => "\\o/",
+        '→' | '➡' => "->",
+        '←' | '⬅' => "<-",
+
+        _ => "?",
+    }
+}
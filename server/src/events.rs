@@ -0,0 +1,192 @@
+//! What happened, for the record.
+//!
+//! Commands operators send and who sent them, whether they reached the workers, and workers
+//! registering, coming and going are appended to an event log. Given a path, the log is kept as a
+//! file of JSON lines that survives restarts and is never rewritten. The latest events are also
+//! held in memory, they answer `/api/events` and fill the portal's history.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::liveness::Liveness;
+
+/// Events kept in memory, older ones are only in the file.
+pub const CAPACITY: usize = 1000;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Event {
+    /// Increasing by one from the first event ever logged.
+    pub id: u64,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// `origin` gave `target`, a worker or every worker with "Broadcast", a new command.
+    Command { target: String, command: String, origin: String },
+    /// The latest command given to a worker reached it.
+    Delivered { worker: String, command: String },
+    /// The latest command given to a worker didn't reach it on the first try.
+    DeliveryFailed { worker: String, command: String, error: String },
+    Registered { worker: String, address: String },
+    Rejected { worker: String, address: String },
+    /// A worker went online, degraded or offline.
+    Liveness { worker: String, liveness: Liveness },
+    /// A worker was taken out of the registry.
+    Removed { worker: String, reason: String },
+    /// A worker sent a firmware update came back running it.
+    Updated { worker: String, version: String },
+    /// A worker sent a firmware update registered again still running `running`, if it said.
+    UpdateFailed { worker: String, version: String, running: Option<String> },
+}
+
+impl EventKind {
+
+    /// The worker, or for commands the target, the event is about.
+    pub fn subject(&self) -> &str {
+        match self {
+            EventKind::Command { target, .. } => target,
+            EventKind::Delivered { worker, .. }
+            | EventKind::DeliveryFailed { worker, .. }
+            | EventKind::Registered { worker, .. }
+            | EventKind::Rejected { worker, .. }
+            | EventKind::Liveness { worker, .. }
+            | EventKind::Removed { worker, .. }
+            | EventKind::Updated { worker, .. }
+            | EventKind::UpdateFailed { worker, .. } => worker,
+        }
+    }
+
+    /// Whether the event has to do with the worker at `mac_address`, broadcasts included.
+    pub fn concerns(&self, mac_address: &str) -> bool {
+        match self {
+            EventKind::Command { target, .. } => target == mac_address || target == "Broadcast",
+            _ => self.subject() == mac_address,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Command { .. } => "Command",
+            EventKind::Delivered { .. } => "Delivered",
+            EventKind::DeliveryFailed { .. } => "Delivery failed",
+            EventKind::Registered { .. } => "Registered",
+            EventKind::Rejected { .. } => "Rejected",
+            EventKind::Liveness { .. } => "Liveness",
+            EventKind::Removed { .. } => "Removed",
+            EventKind::Updated { .. } => "Updated",
+            EventKind::UpdateFailed { .. } => "Update failed",
+        }
+    }
+
+    /// Everything besides the subject, in a few words.
+    pub fn details(&self) -> String {
+        match self {
+            EventKind::Command { command, origin, .. } => format!("{} by {}", command, origin),
+            EventKind::Delivered { command, .. } => command.clone(),
+            EventKind::DeliveryFailed { command, error, .. } => format!("{}: {}", command, error),
+            EventKind::Registered { address, .. } | EventKind::Rejected { address, .. } => format!("from {}", address),
+            EventKind::Liveness { liveness, .. } => format!("{:?}", liveness).to_lowercase(),
+            EventKind::Removed { reason, .. } => reason.clone(),
+            EventKind::Updated { version, .. } => format!("to {}", version),
+            EventKind::UpdateFailed { version, running, .. } => match running {
+                Some(running) => format!("to {}, still running {}", version, running),
+                None => format!("to {}", version),
+            },
+        }
+    }
+}
+
+pub struct EventLog {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    recent: VecDeque<Event>,
+    next_id: u64,
+    file: Option<File>,
+}
+
+impl EventLog {
+
+    /// A log that is lost when the server stops.
+    pub fn in_memory() -> Self {
+        EventLog { inner: Mutex::new(Inner { recent: VecDeque::new(), next_id: 1, file: None }) }
+    }
+
+    /// Append to the log at `path`, picking up the events already in it.
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let mut recent = VecDeque::new();
+        let mut next_id = 1;
+
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                // A line cut short by a crash is skipped, not fatal
+                if let Ok(event) = serde_json::from_str::<Event>(&line?) {
+                    next_id = event.id + 1;
+                    if recent.len() == CAPACITY {
+                        recent.pop_front();
+                    }
+                    recent.push_back(event);
+                }
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(EventLog { inner: Mutex::new(Inner { recent, next_id, file: Some(file) }) })
+    }
+
+    pub fn record(&self, kind: EventKind) -> Event {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut inner = self.inner.lock().unwrap();
+        let event = Event { id: inner.next_id, time, kind };
+        inner.next_id += 1;
+
+        if let Some(file) = &mut inner.file {
+            let line = serde_json::to_string(&event).unwrap() + "\n";
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                tracing::error!(event = event.id, "Failed to write event: {}", e);
+            }
+        }
+
+        if inner.recent.len() == CAPACITY {
+            inner.recent.pop_front();
+        }
+        inner.recent.push_back(event.clone());
+
+        event
+    }
+
+    /// Events in memory after the one with id `since`, only those concerning `worker` if given.
+    pub fn query(&self, since: Option<u64>, worker: Option<&str>) -> Vec<Event> {
+        let inner = self.inner.lock().unwrap();
+        inner.recent.iter()
+            .filter(|e| since.is_none_or(|id| e.id > id))
+            .filter(|e| worker.is_none_or(|mac| e.kind.concerns(mac)))
+            .cloned()
+            .collect()
+    }
+
+    /// The last `count` events, newest first.
+    pub fn latest(&self, count: usize) -> Vec<Event> {
+        let inner = self.inner.lock().unwrap();
+        inner.recent.iter().rev().take(count).cloned().collect()
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
@@ -0,0 +1,208 @@
+//! Firmware images workers are updated to.
+//!
+//! Admins upload images with `POST /firmware?version=<version>&signature=<hex>`, the body being
+//! the image as built. The version has to be the one the image reports in its telemetry, that is
+//! how the server tells the update worked. Given a directory, images are kept there as
+//! `<version>.bin` next to a `<version>.json` with what is known about them, and picked up again
+//! on restart. With a release key configured, only images signed with it are taken, or picked up.
+//!
+//! `POST /ota` then sends a worker, or every worker, an `OTA` directive (see the `ota` crate).
+//! Workers download the image from `/firmware/<version>`, which needs no credentials: images are
+//! no secret, and workers check them against the digest and signature of the directive anyway.
+//! The directive points at the address the worker was reached on unless `firmware_url` says
+//! otherwise, which a portal served over HTTPS with a certificate workers can't check needs.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use ota::{DIGEST_LEN, KEY_LEN, SIGNATURE_LEN};
+
+/// Largest image taken, more than fits an OTA partition of the boards.
+pub const MAX_IMAGE_SIZE: usize = 4 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Image {
+    pub version: String,
+    pub sha256: [u8; DIGEST_LEN],
+    pub signature: Option<[u8; SIGNATURE_LEN]>,
+    /// Seconds since the Unix epoch.
+    pub uploaded: u64,
+    pub data: Arc<Vec<u8>>,
+}
+
+impl Image {
+
+    pub fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+/// An image as listed by `/api/firmware` and described next to it on disk.
+#[derive(Serialize, Deserialize)]
+pub struct ImageInfo {
+    pub version: String,
+    pub size: u64,
+    pub sha256: String,
+    pub signature: Option<String>,
+    pub uploaded: u64,
+}
+
+impl From<&Image> for ImageInfo {
+    fn from(image: &Image) -> Self {
+        ImageInfo {
+            version: image.version.clone(),
+            size: image.size(),
+            sha256: ota::to_hex(&image.sha256),
+            signature: image.signature.map(|s| ota::to_hex(&s)),
+            uploaded: image.uploaded,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UploadError {
+    /// Versions end up in file names and URLs, so they are kept to letters, digits and `.+-_`.
+    InvalidVersion,
+    TooLarge,
+    /// A release key is configured and the image isn't signed with it.
+    Unsigned,
+    Signature,
+    Storage(String),
+}
+
+pub struct FirmwareStore {
+    images: RwLock<BTreeMap<String, Image>>,
+    dir: Option<PathBuf>,
+    release_key: Option<[u8; KEY_LEN]>,
+}
+
+impl FirmwareStore {
+
+    /// A store that forgets its images when the server stops.
+    pub fn in_memory(release_key: Option<[u8; KEY_LEN]>) -> Self {
+        FirmwareStore { images: RwLock::new(BTreeMap::new()), dir: None, release_key }
+    }
+
+    /// Keep images in `dir`, picking up those already there.
+    pub fn open(dir: &Path, release_key: Option<[u8; KEY_LEN]>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let mut images = BTreeMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                // Half written or tampered with images are left out, not fatal, as are images the
+                // release key wouldn't take now
+                match load(&path, release_key.as_ref()) {
+                    Some(image) => { images.insert(image.version.clone(), image); },
+                    None => tracing::warn!("Skipping firmware {}", path.display()),
+                }
+            }
+        }
+
+        Ok(FirmwareStore { images: RwLock::new(images), dir: Some(dir.to_path_buf()), release_key })
+    }
+
+    /// Take the image of `version`, replacing any image of the same version.
+    pub fn add(&self, version: &str, data: Vec<u8>, signature: Option<[u8; SIGNATURE_LEN]>) -> Result<ImageInfo, UploadError> {
+        let valid = !version.is_empty() && version.len() <= 32
+            && version.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-' | '_'));
+        if !valid {
+            return Err(UploadError::InvalidVersion);
+        }
+        if data.len() > MAX_IMAGE_SIZE {
+            return Err(UploadError::TooLarge);
+        }
+
+        let sha256 = ota::digest(&data);
+        if let Some(release_key) = &self.release_key {
+            let signature = signature.ok_or(UploadError::Unsigned)?;
+            if !ota::verify(release_key, &sha256, &signature) {
+                return Err(UploadError::Signature);
+            }
+        }
+
+        let uploaded = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let image = Image { version: version.to_string(), sha256, signature, uploaded, data: Arc::new(data) };
+
+        if let Some(dir) = &self.dir {
+            store(dir, &image).map_err(|e| UploadError::Storage(e.to_string()))?;
+        }
+
+        let info = ImageInfo::from(&image);
+        self.images.write().unwrap().insert(image.version.clone(), image);
+        Ok(info)
+    }
+
+    pub fn get(&self, version: &str) -> Option<Image> {
+        self.images.read().unwrap().get(version).cloned()
+    }
+
+    /// Every image, by version.
+    pub fn list(&self) -> Vec<ImageInfo> {
+        self.images.read().unwrap().values().map(ImageInfo::from).collect()
+    }
+}
+
+impl Default for FirmwareStore {
+    fn default() -> Self {
+        Self::in_memory(None)
+    }
+}
+
+// The image goes first, so a description never points at an image that isn't all there
+fn store(dir: &Path, image: &Image) -> std::io::Result<()> {
+    File::create(dir.join(format!("{}.bin", image.version)))?.write_all(&image.data)?;
+    let info = serde_json::to_string(&ImageInfo::from(image)).unwrap();
+    std::fs::write(dir.join(format!("{}.json", image.version)), info)
+}
+
+fn load(info_path: &Path, release_key: Option<&[u8; KEY_LEN]>) -> Option<Image> {
+    let info: ImageInfo = serde_json::from_str(&std::fs::read_to_string(info_path).ok()?).ok()?;
+    let data = std::fs::read(info_path.with_extension("bin")).ok()?;
+
+    let sha256 = ota::digest(&data);
+    if ota::to_hex(&sha256) != info.sha256 {
+        return None;
+    }
+    let signature = match &info.signature {
+        Some(hex) => Some(ota::parse_hex(hex)?),
+        None => None,
+    };
+    if let Some(release_key) = release_key {
+        if !ota::verify(release_key, &sha256, &signature?) {
+            return None;
+        }
+    }
+
+    Some(Image { version: info.version, sha256, signature, uploaded: info.uploaded, data: Arc::new(data) })
+}
+
+/// Where workers download images from.
+#[derive(Clone, Debug)]
+pub struct FirmwareLocation {
+    /// Given by `firmware_url`, such as `http://192.168.4.209:8080`.
+    pub base_url: Option<String>,
+    /// Port and scheme of the portal, for workers to reach it on the address they were reached from.
+    pub port: u16,
+    pub https: bool,
+}
+
+impl FirmwareLocation {
+
+    /// The URL of the image of `version` for a worker reached from `server_ip`.
+    pub fn url(&self, server_ip: IpAddr, version: &str) -> String {
+        let base = match &self.base_url {
+            Some(base) => base.trim_end_matches('/').to_string(),
+            None => format!("{}://{}", if self.https { "https" } else { "http" }, SocketAddr::new(server_ip, self.port)),
+        };
+        format!("{}/firmware/{}", base, version)
+    }
+}
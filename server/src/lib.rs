@@ -0,0 +1,1396 @@
+pub mod auth;
+pub mod content;
+pub mod events;
+pub mod firmware;
+pub mod limits;
+pub mod liveness;
+pub mod logging;
+pub mod metrics;
+pub mod registry;
+pub mod settings;
+pub mod worker_logs;
+
+use axum::{
+    routing::get,
+    Router,
+};
+
+use phf::phf_map;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use axum::response::Html;
+use axum::extract;
+use axum::Json;
+use axum::routing::post;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use sailfish::TemplateOnce;
+
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::io::Error;
+use tokio::time::Duration;
+use tokio::time::Instant;
+use tokio::time::timeout;
+
+use axum::extract::State;
+use axum::Extension;
+use axum::middleware;
+use axum::body::Bytes;
+use axum::extract::DefaultBodyLimit;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+
+use auth::{Caller, Role};
+use content::ContentPolicy;
+use events::{Event, EventKind};
+use firmware::{FirmwareLocation, ImageInfo, UploadError};
+use limits::RateLimiter;
+use liveness::{Heartbeat, Liveness, LivenessPolicy};
+use registry::Registry;
+use settings::ServerConfig;
+use worker_logs::WorkerLogEntry;
+
+use registration::Session;
+use telemetry::{LogLine, Telemetry};
+
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+
+use std::str::FromStr;
+
+//use std::collections::HashSet;
+
+static PERSISTENT_WORKERS: phf::Map<&'static str, &'static str> = phf_map! {
+    "EC:DA:3B:BF:46:9C" => "Georgia",
+    "EC:DA:3B:BF:49:2C" => "Asher",
+    "EC:DA:3B:BF:39:74" => "Lila",
+};
+
+
+#[derive(Clone)]
+enum MicroCommand {
+    Ping(MicroPing),
+    Message(MicroMessage),
+    Timer(MicroTimer),
+    Animation(MicroAnimation),
+    Scene(MicroScene),
+    Ota(MicroOta),
+}
+
+impl MicroCommand {
+    // Directives to workers holding a session are sealed, everything else goes out as is
+    async fn execute(&self, worker_connection: &mut tokio::net::TcpStream, session: Option<&Mutex<Session>>) -> Result<(),Error> {
+        let directive = match self {
+            MicroCommand::Ping(cmd) => cmd.directive(),
+            MicroCommand::Message(cmd) => cmd.directive(),
+            MicroCommand::Timer(cmd) => cmd.directive(),
+            MicroCommand::Animation(cmd) => cmd.directive(),
+            MicroCommand::Scene(cmd) => cmd.directive(),
+            MicroCommand::Ota(cmd) => cmd.directive(worker_connection.local_addr()?.ip()),
+        };
+
+        let directive = match session {
+            Some(session) => session.lock().unwrap().seal(&directive),
+            None => directive,
+        };
+
+        worker_connection.write_all(&directive.into_bytes()).await
+    }
+
+    // The type of command, for metrics
+    fn kind(&self) -> &'static str {
+        match self {
+            MicroCommand::Ping(_) => "ping",
+            MicroCommand::Message(_) => "message",
+            MicroCommand::Timer(_) => "timer",
+            MicroCommand::Animation(_) => "animation",
+            MicroCommand::Scene(_) => "scene",
+            MicroCommand::Ota(_) => "ota",
+        }
+    }
+
+    // A few words on the command for the event log
+    fn describe(&self) -> String {
+        match self {
+            MicroCommand::Ping(_) => "ping".to_string(),
+            MicroCommand::Message(cmd) => format!("message {:?}", cmd.message),
+            MicroCommand::Timer(cmd) => format!("timer {} min", cmd.duration.as_secs() / 60),
+            MicroCommand::Animation(cmd) => format!("animation {}", cmd.animation),
+            MicroCommand::Scene(cmd) => match &cmd.timer {
+                Some(timer) => format!("scene {} {:?} with a {} min timer", cmd.animation, cmd.message, timer.duration.as_secs() / 60),
+                None => format!("scene {} {:?}", cmd.animation, cmd.message),
+            },
+            MicroCommand::Ota(cmd) => format!("update to {}", cmd.version),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MicroPing {
+}
+
+impl MicroPing {
+    fn directive(&self) -> String {
+        trace!("Executing Ping Command");
+        "PING".to_string()
+    }
+}
+
+#[derive(Clone)]
+struct MicroMessage {
+    message: String,
+}
+
+impl MicroMessage {
+
+    fn directive(&self) -> String {
+        debug!("Executing Message Command");
+
+        "MESSAGE ".to_string() + &self.message
+    }
+
+    fn raw(&self) -> String {
+        self.message.to_string()
+    }
+
+    fn extract_last_message(cmd: &Option<MicroCommand>) -> String {
+        if let Some(MicroCommand::Message(c)) = cmd {
+            c.raw()
+        } else {
+            "".to_string()
+        }
+    }
+}
+
+
+#[derive(Clone)]
+struct MicroTimer {
+    start: tokio::time::Instant,
+    duration: tokio::time::Duration,
+}
+
+
+impl MicroTimer {
+
+    fn directive(&self) -> String {
+        debug!("Executing Timer Command");
+        "TIMER ".to_string() + &self.countdown()
+    }
+
+    // remaining/total in seconds, as expected by the client
+    fn countdown(&self) -> String {
+        let remaining = self.duration.checked_sub(tokio::time::Instant::now().duration_since(self.start)).unwrap_or(tokio::time::Duration::new(0,0));
+        remaining.as_secs().to_string() + "/" + &self.duration.as_secs().to_string()
+    }
+
+    fn raw(&self) -> String {
+        match self.duration.checked_sub(tokio::time::Instant::now().duration_since(self.start)) {
+            Some(remaining) => remaining.as_secs().to_string(),
+            None => "00:00".to_string(),
+        }
+    }
+
+    fn extract_remaining_time(cmd: &Option<MicroCommand>) -> String {
+        if let Some(MicroCommand::Timer(c)) = cmd {
+            c.raw()
+        } else {
+            "00:00".to_string()
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MicroAnimation {
+    animation: String
+}
+
+impl MicroAnimation {
+
+    fn directive(&self) -> String {
+        debug!("Executing Animate Command");
+        "ANIMATE ".to_string() + &self.animation
+    }
+
+    fn raw(&self) -> String {
+        self.animation.to_string()
+    }
+
+    fn extract_animation(cmd: &Option<MicroCommand>) -> String {
+        if let Some(MicroCommand::Animation(c)) = cmd {
+            c.raw()
+        } else {
+            "".to_string()
+        }
+    }
+}
+
+// An animation with an optional timer badge and text drawn over it. Empty layers are left out.
+#[derive(Clone)]
+struct MicroScene {
+    animation: String,
+    timer: Option<MicroTimer>,
+    message: String,
+}
+
+impl MicroScene {
+
+    fn directive(&self) -> String {
+        debug!("Executing Scene Command");
+        let timer = self.timer.as_ref().map(|t| t.countdown()).unwrap_or_default();
+        "SCENE ".to_string() + &self.animation + "|" + &timer + "|" + &self.message
+    }
+
+    fn extract_animation(cmd: &Option<MicroCommand>) -> String {
+        if let Some(MicroCommand::Scene(c)) = cmd {
+            c.animation.to_string()
+        } else {
+            "".to_string()
+        }
+    }
+
+    fn extract_message(cmd: &Option<MicroCommand>) -> String {
+        if let Some(MicroCommand::Scene(c)) = cmd {
+            c.message.to_string()
+        } else {
+            "".to_string()
+        }
+    }
+}
+
+// A firmware image to boot, the URL depends on the address the worker is reached from
+#[derive(Clone)]
+struct MicroOta {
+    version: String,
+    size: u64,
+    sha256: [u8; ota::DIGEST_LEN],
+    signature: Option<[u8; ota::SIGNATURE_LEN]>,
+    location: FirmwareLocation,
+}
+
+impl MicroOta {
+    fn directive(&self, server_ip: IpAddr) -> String {
+        ota::Update {
+            version: self.version.clone(),
+            size: self.size,
+            sha256: self.sha256,
+            signature: self.signature,
+            url: self.location.url(server_ip, &self.version),
+        }.directive()
+    }
+}
+
+#[derive(Clone)]
+pub struct MicroWorker {
+    pub mac_address: String,
+    pub alias: Option<String>,
+    pub ip_address: Option<SocketAddr>,
+    pub active: bool,
+    pub persistent: bool,
+    current_cmd: Option<MicroCommand>,
+    // Id of the event that logged `current_cmd`, and of the last command that was delivered or failed to be
+    command_id: u64,
+    delivered_id: u64,
+    failed_id: u64,
+    // A firmware update sent once ahead of `current_cmd`, then the version it went out with until
+    // the worker reports back
+    update: Option<MicroOta>,
+    updating_to: Option<String>,
+    // Shared by the clones the broadcast loop works on, so the counter keeps growing
+    session: Option<Arc<Mutex<Session>>>,
+    /// The latest report from the worker, if it sends any.
+    pub telemetry: Option<Telemetry>,
+    pub heartbeat: Heartbeat,
+}
+
+impl MicroWorker {
+
+    fn get_alias(mac_address: &str) -> Option<String> {
+        PERSISTENT_WORKERS.get(mac_address).map(|a| a.to_string())
+    }
+
+    fn new(mac_address: String, ip_address: Option<SocketAddr>) -> Self {
+        Self {
+            alias: MicroWorker::get_alias(&mac_address),
+            mac_address,
+            ip_address,
+            active: true,
+            persistent: false,
+            current_cmd: None,
+            command_id: 0,
+            delivered_id: 0,
+            failed_id: 0,
+            update: None,
+            updating_to: None,
+            session: None,
+            telemetry: None,
+            heartbeat: Heartbeat::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.alias {
+            Some(a) => a,
+            None => &self.mac_address,
+        }
+    }
+
+    // Name colour in the portal
+    fn liveness_color(&self) -> &'static str {
+        match self.heartbeat.liveness {
+            Liveness::Online => "green",
+            Liveness::Degraded => "orange",
+            Liveness::Offline => "red",
+        }
+    }
+
+    fn last_seen(&self) -> String {
+        match self.heartbeat.last_seen {
+            Some(t) => format!("{} s ago", t.elapsed().as_secs()),
+            None => "never".to_string(),
+        }
+    }
+
+    // Signal, uptime, heap, firmware, reset reason and display for the portal
+    fn telemetry_cells(&self) -> [String; 6] {
+        let Some(t) = &self.telemetry else {
+            return Default::default();
+        };
+        let text = |v: &Option<String>| v.clone().unwrap_or_default();
+
+        [
+            t.rssi.map(|r| format!("{} dBm", r)).unwrap_or_default(),
+            t.uptime.map(|u| format!("{}:{:02}:{:02}", u / 3600, u / 60 % 60, u % 60)).unwrap_or_default(),
+            t.free_heap.map(|h| format!("{} KiB", h / 1024)).unwrap_or_default(),
+            match &self.updating_to {
+                Some(version) => format!("{} → {}", text(&t.firmware), version),
+                None => text(&t.firmware),
+            },
+            text(&t.reset_reason),
+            text(&t.display),
+        ]
+    }
+}
+
+/// A worker as listed by `/api/workers`.
+#[derive(Serialize)]
+struct WorkerStatus {
+    mac_address: String,
+    alias: Option<String>,
+    ip_address: Option<String>,
+    active: bool,
+    persistent: bool,
+    liveness: Liveness,
+    /// Seconds since the worker was last heard from.
+    last_seen_s: Option<u64>,
+    missed_sweeps: u32,
+    telemetry: Option<TelemetryStatus>,
+    /// Firmware the worker was sent and hasn't reported running yet.
+    updating_to: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TelemetryStatus {
+    rssi: Option<i32>,
+    uptime_s: Option<u64>,
+    reset_reason: Option<String>,
+    free_heap: Option<u32>,
+    firmware: Option<String>,
+    display: Option<String>,
+}
+
+impl From<&MicroWorker> for WorkerStatus {
+    fn from(worker: &MicroWorker) -> Self {
+        WorkerStatus {
+            mac_address: worker.mac_address.clone(),
+            alias: worker.alias.clone(),
+            ip_address: worker.ip_address.map(|a| a.to_string()),
+            active: worker.active,
+            persistent: worker.persistent,
+            liveness: worker.heartbeat.liveness,
+            last_seen_s: worker.heartbeat.last_seen.map(|t| t.elapsed().as_secs()),
+            missed_sweeps: worker.heartbeat.missed,
+            telemetry: worker.telemetry.clone().map(|t| TelemetryStatus {
+                rssi: t.rssi,
+                uptime_s: t.uptime,
+                reset_reason: t.reset_reason,
+                free_heap: t.free_heap,
+                firmware: t.firmware,
+                display: t.display,
+            }),
+            updating_to: worker.updating_to.clone(),
+        }
+    }
+}
+
+
+struct AppState {
+    registry: Registry,
+    content: ContentPolicy,
+    firmware_location: FirmwareLocation,
+}
+
+#[derive(Deserialize)]
+struct MessageRequest {
+    id: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct TimerRequest {
+    id: String,
+    duration: String,
+}
+
+#[derive(Deserialize)]
+struct AnimationRequest {
+    id: String,
+    animation: String,
+}
+
+#[derive(Deserialize)]
+struct SceneRequest {
+    id: String,
+    animation: String,
+    duration: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct KeyRequest {
+    id: String,
+    key: String,
+}
+
+#[derive(Deserialize)]
+struct RemoveRequest {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct OtaRequest {
+    id: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    version: String,
+    signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EventQuery {
+    since: Option<u64>,
+    worker: Option<String>,
+}
+
+#[derive(Serialize)]
+struct RequestReceipt {
+    status: String,
+}
+
+pub struct MicroManager {
+    // By MAC address
+    workers: HashMap<String, MicroWorker>,
+    // MAC addresses in listing order, persistent workers first
+    order: Vec<String>,
+    // Pre-shared registration keys by MAC address
+    keys: HashMap<String, Vec<u8>>,
+}
+
+impl MicroManager {
+
+    pub fn new() -> Self {
+        let mut micro_manager = Self { workers: HashMap::new(), order: Vec::new(), keys: HashMap::new() };
+
+        for (mac_address, alias) in PERSISTENT_WORKERS.entries() {
+            micro_manager.insert( MicroWorker {
+                mac_address: mac_address.to_string(),
+                alias: Some(alias.to_string()),
+                ip_address: None,
+                active: false,
+                persistent: true,
+                current_cmd: None,
+                command_id: 0,
+                delivered_id: 0,
+                failed_id: 0,
+                update: None,
+                updating_to: None,
+                session: None,
+                telemetry: None,
+                heartbeat: Heartbeat::new(),
+            });
+        }
+
+        micro_manager
+    }
+
+    fn insert(&mut self, worker: MicroWorker) {
+        self.order.push(worker.mac_address.clone());
+        self.workers.insert(worker.mac_address.clone(), worker);
+    }
+
+    fn forget(&mut self, mac_address: &str) {
+        self.workers.remove(mac_address);
+        self.order.retain(|m| m != mac_address);
+    }
+
+    /// Every worker, in the order the portal lists them.
+    pub fn workers(&self) -> impl Iterator<Item = &MicroWorker> {
+        self.order.iter().filter_map(|m| self.workers.get(m))
+    }
+
+    fn workers_mut(&mut self) -> impl Iterator<Item = &mut MicroWorker> {
+        self.workers.values_mut()
+    }
+
+    /// Require the worker with `mac_address` to prove it holds `key` when registering.
+    pub fn set_key(&mut self, mac_address: &str, key: Vec<u8>) {
+        self.keys.insert(mac_address.to_string(), key);
+    }
+
+    pub fn remove_key(&mut self, mac_address: &str) {
+        self.keys.remove(mac_address);
+    }
+
+    pub fn get_key(&self, mac_address: &str) -> Option<&[u8]> {
+        self.keys.get(mac_address).map(|k| k.as_slice())
+    }
+
+    fn add_worker(&mut self, mac_address: String, ip_address: SocketAddr, session: Option<Arc<Mutex<Session>>>) {
+        if let Some(w) = self.get_worker_mut(&mac_address) {
+            info!("Setting worker {} to active", w.name());
+            w.active = true;
+            w.ip_address = Some(ip_address);
+            w.session = session;
+            w.heartbeat.seen(Instant::now());
+        } else {
+            let mut worker = MicroWorker::new(mac_address, Some(ip_address));
+            worker.session = session;
+            worker.heartbeat.seen(Instant::now());
+            self.insert(worker);
+        }
+    }
+
+    // Whether the worker was degraded or offline until now
+    fn heard_from(&mut self, mac_address: &str) -> bool {
+        let Some(w) = self.get_worker_mut(mac_address) else { return false };
+
+        let returned = w.heartbeat.liveness != Liveness::Online;
+        if returned {
+            info!("Worker {} is back online", w.name());
+        }
+        w.heartbeat.seen(Instant::now());
+        w.active = true;
+        returned
+    }
+
+    // The worker's new liveness, if the missed sweep changed it
+    fn missed_sweep(&mut self, mac_address: &str, policy: &LivenessPolicy) -> Option<Liveness> {
+        let w = self.get_worker_mut(mac_address)?;
+
+        let before = w.heartbeat.liveness;
+        w.heartbeat.missed(policy, Instant::now());
+        w.active = w.heartbeat.liveness != Liveness::Offline;
+
+        if w.heartbeat.liveness == before {
+            return None;
+        }
+        warn!("Worker {} is {:?} after {} missed sweep(s)", w.name(), w.heartbeat.liveness, w.heartbeat.missed);
+        Some(w.heartbeat.liveness)
+    }
+
+    // Note how the command logged as `command_id` fared, true the first time for each outcome
+    fn delivery(&mut self, mac_address: &str, command_id: u64, delivered: bool) -> bool {
+        let Some(w) = self.get_worker_mut(mac_address) else { return false };
+
+        let reported = if delivered { &mut w.delivered_id } else { &mut w.failed_id };
+        if *reported >= command_id {
+            return false;
+        }
+        *reported = command_id;
+        true
+    }
+
+    // Forget workers that aren't persistent once they have been offline for too long, returns their MAC addresses
+    fn expire_workers(&mut self, policy: &LivenessPolicy) -> Vec<String> {
+        let now = Instant::now();
+        let expired: Vec<String> = self.workers()
+            .filter(|w| !w.persistent && w.heartbeat.expired(policy, now))
+            .map(|w| w.mac_address.clone())
+            .collect();
+
+        for mac_address in &expired {
+            info!(worker = %mac_address, "Forgetting worker after {} s offline", policy.retention.as_secs());
+            self.forget(mac_address);
+        }
+        expired
+    }
+
+    // The update given as `version` reached the worker, it is judged by what the worker reports next
+    fn update_sent(&mut self, mac_address: &str, version: &str) {
+        let Some(w) = self.get_worker_mut(mac_address) else { return };
+
+        if w.update.as_ref().is_some_and(|u| u.version == version) {
+            w.update = None;
+            w.updating_to = Some(version.to_string());
+        }
+    }
+
+    // How the update the worker was sent turned out, if the report tells. Running anything else
+    // only counts as failed when the worker registers, it may not have got to the update before.
+    fn update_telemetry(&mut self, mac_address: &str, telemetry: Telemetry, registering: bool) -> Option<UpdateOutcome> {
+        let w = self.get_worker_mut(mac_address)?;
+
+        let running = telemetry.firmware.clone();
+        w.telemetry = Some(telemetry);
+
+        let target = w.updating_to.as_deref()?;
+        if running.as_deref() == Some(target) {
+            Some(UpdateOutcome::Updated { version: w.updating_to.take()? })
+        } else if registering {
+            Some(UpdateOutcome::Failed { version: w.updating_to.take()?, running })
+        } else {
+            None
+        }
+    }
+
+    // Removal on request, persistent workers stay listed but aren't contacted until they register again
+    fn remove_worker(&mut self, mac_address: &str) {
+        if let Some(w) = self.get_worker_mut(mac_address) {
+            if w.persistent {
+                w.active = false;
+                w.ip_address = None;
+                w.heartbeat = Heartbeat::new();
+            } else {
+                self.forget(mac_address);
+            }
+        }
+    }
+
+    fn get_worker_mut(&mut self, mac_address: &str) -> Option<&mut MicroWorker> {
+        self.workers.get_mut(mac_address)
+    }
+
+    pub fn get_worker(&self, mac_address: &str) -> Option<&MicroWorker> {
+        self.workers.get(mac_address)
+    }
+
+}
+
+enum UpdateOutcome {
+    Updated { version: String },
+    Failed { version: String, running: Option<String> },
+}
+
+impl Default for MicroManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Events shown in the portal's history
+const HISTORY_LENGTH: usize = 50;
+
+#[derive(TemplateOnce)] // automatically implement `TemplateOnce` trait
+#[template(path = "portal.stpl")] // specify the path to template
+struct PortalTemplate<'a> {
+    workers: &'a [&'a MicroWorker],
+    events: Vec<Event>,
+    can_operate: bool,
+}
+
+// How long a worker gets to send each line of the registration exchange
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn read_line<R: tokio::io::AsyncBufRead + Unpin>(reader: &mut R) -> Option<String> {
+    let mut line = String::new();
+    match timeout(REGISTRATION_TIMEOUT, reader.take(1024).read_line(&mut line)).await {
+        Ok(Ok(n)) if n > 0 => Some(line),
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => {
+            warn!("Failed to read from socket: {}", e);
+            None
+        }
+        Err(_) => {
+            warn!("Registration timed out");
+            None
+        }
+    }
+}
+
+async fn register_worker(registry: Registry, mut socket: tokio::net::TcpStream, worker_port: u16, open_registration: bool) {
+    let address = socket.peer_addr().unwrap();
+    debug!("New connection");
+
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::new(reader);
+
+    let Some(message) = read_line(&mut reader).await else {
+        registry.metrics().registrations.with_label_values(&["invalid"]).inc();
+        return;
+    };
+    trace!("Message: {}", message.trim_end());
+
+    let mut parts = message.split_ascii_whitespace();
+    let mac_address = match (parts.next(), parts.next()) {
+        (Some(registration::REGISTER), Some(mac_address)) => mac_address.to_string(),
+        _ => {
+            warn!("Invalid Request");
+            registry.metrics().registrations.with_label_values(&["invalid"]).inc();
+            return;
+        }
+    };
+    tracing::Span::current().record("worker", &mac_address);
+
+    // Workers may name the port they listen on, which lets several
+    // virtual workers share one host. Boards always use the default.
+    let rx_port = parts.next().and_then(|p| u16::from_str(p).ok()).unwrap_or(worker_port);
+    let rx_address = SocketAddr::new(address.ip(), rx_port);
+
+    let key = registry.read().get_key(&mac_address).map(|k| k.to_vec());
+
+    // Workers proving their key get their directives sealed, and have to seal their replies
+    let (accepted, session) = match key {
+        Some(key) => {
+            let nonce: [u8; registration::NONCE_LEN] = rand::random();
+            if writer.write_all(registration::challenge(&nonce).as_bytes()).await.is_err() {
+                return;
+            }
+            match read_line(&mut reader).await {
+                Some(response) if registration::verify(&key, &nonce, &mac_address, &response) => {
+                    (true, Some(Arc::new(Mutex::new(Session::new(&key, &nonce, &mac_address)))))
+                },
+                _ => (false, None),
+            }
+        }
+        None => (open_registration, None),
+    };
+
+    if !accepted {
+        warn!("Rejected registration");
+        registry.metrics().registrations.with_label_values(&["rejected"]).inc();
+        registry.events().record(EventKind::Rejected { worker: mac_address, address: address.ip().to_string() });
+        let _ = writer.write_all(format!("{}\n", registration::REJECTED).as_bytes()).await;
+        return;
+    }
+
+    info!(%rx_address, "Registering MicroWorker");
+    registry.metrics().registrations.with_label_values(&["accepted"]).inc();
+    registry.update(|m| m.add_worker(mac_address.clone(), rx_address, session.clone()));
+    registry.events().record(EventKind::Registered { worker: mac_address.clone(), address: address.ip().to_string() });
+
+    // Older workers hang up right after registering, so this may well go nowhere
+    if writer.write_all(format!("{}\n", registration::ACCEPTED).as_bytes()).await.is_err() {
+        return;
+    }
+
+    // Newer ones follow up with their first report
+    let report = read_line(&mut reader).await.and_then(|line| open_reply(session.as_deref(), line));
+    if let Some(telemetry) = report.as_deref().and_then(Telemetry::parse) {
+        note_telemetry(&registry, &mac_address, telemetry, true);
+    }
+}
+
+// Keep a report, and log how a firmware update went if it tells
+fn note_telemetry(registry: &Registry, mac_address: &str, telemetry: Telemetry, registering: bool) {
+    let worker = mac_address.to_string();
+    match registry.record(|m| m.update_telemetry(mac_address, telemetry, registering)) {
+        Some(UpdateOutcome::Updated { version }) => {
+            info!("Updated to {}", version);
+            registry.metrics().updates.with_label_values(&["updated"]).inc();
+            registry.events().record(EventKind::Updated { worker, version });
+        },
+        Some(UpdateOutcome::Failed { version, running }) => {
+            warn!("Update to {} failed, running {}", version, running.as_deref().unwrap_or("unknown firmware"));
+            registry.metrics().updates.with_label_values(&["failed"]).inc();
+            registry.events().record(EventKind::UpdateFailed { worker, version, running });
+        },
+        None => (),
+    }
+}
+
+// Most a worker may answer a directive with, a report and the lines it logged since the last
+// one. Sealed lines take a little over twice the room.
+const REPLY_LIMIT: u64 = 40 * 1024;
+
+// A line a worker answered with. Workers holding a session seal every line, anything else from
+// them is dropped.
+fn open_reply(session: Option<&Mutex<Session>>, line: String) -> Option<String> {
+    match session {
+        Some(session) => {
+            let opened = session.lock().unwrap().open_reply(&line);
+            if opened.is_none() {
+                warn!("Dropping reply that isn't sealed for this session");
+            }
+            opened
+        },
+        None => Some(line),
+    }
+}
+
+// After a directive the worker may answer with a report and forward what it logged, ending our
+// side tells it we are done. Forwarded lines are logged here, in the worker's span, and kept.
+async fn read_reply(registry: &Registry, mac_address: &str, session: Option<&Mutex<Session>>, mut worker_connection: tokio::net::TcpStream, reply_timeout: Duration) -> Option<Telemetry> {
+    worker_connection.shutdown().await.ok()?;
+
+    let deadline = Instant::now() + reply_timeout;
+    let mut reader = BufReader::new(worker_connection).take(REPLY_LIMIT);
+    let mut telemetry = None;
+    loop {
+        let mut line = String::new();
+        match tokio::time::timeout_at(deadline, reader.read_line(&mut line)).await {
+            Ok(Ok(n)) if n > 0 => (),
+            _ => break,
+        }
+        let Some(line) = open_reply(session, line) else { continue };
+
+        if let Some(report) = Telemetry::parse(&line) {
+            telemetry = Some(report);
+        } else if let Some(log) = LogLine::parse(&line) {
+            logging::forwarded(&log.level, &log.message);
+            registry.worker_logs().record(mac_address, log);
+        }
+    }
+    telemetry
+}
+
+
+async fn portal_handler(State(state): State<Arc<AppState>>, Extension(role): Extension<Role>) -> Html<String> {
+
+    let micro_manager = state.registry.read();
+    let workers: Vec<&MicroWorker> = micro_manager.workers().collect();
+    let portal = PortalTemplate {
+        workers: &workers,
+        events: state.registry.events().latest(HISTORY_LENGTH),
+        can_operate: role >= Role::Operator,
+    };
+
+    let html_content = portal.render_once().unwrap();
+    Html(html_content)
+}
+
+async fn workers_handler(State(state): State<Arc<AppState>>) -> Json<Vec<WorkerStatus>> {
+    Json(state.registry.read().workers().map(WorkerStatus::from).collect())
+}
+
+/// Fleet health in the Prometheus text format, see [`metrics`].
+async fn metrics_handler(State(state): State<Arc<AppState>>) -> axum::response::Response {
+    let body = state.registry.metrics().render(&state.registry.read());
+    metrics::respond(body)
+}
+
+/// The latest lines the worker at `mac` forwarded, oldest first.
+async fn worker_logs_handler(State(state): State<Arc<AppState>>, extract::Path(mac): extract::Path<String>) -> Json<Vec<WorkerLogEntry>> {
+    Json(state.registry.worker_logs().lines(&mac))
+}
+
+/// Logged events after the id `since`, only those concerning `worker` if given.
+async fn events_handler(State(state): State<Arc<AppState>>, extract::Query(query): extract::Query<EventQuery>) -> Json<Vec<Event>> {
+    Json(state.registry.events().query(query.since, query.worker.as_deref()))
+}
+
+// Give `id`, or every worker for "Broadcast", a new command of type `kind` and log it as
+// `description`. The id of the logged event identifies the command from then on.
+fn assign(registry: &Registry, caller: &Caller, id: &str, kind: &str, description: String, command: impl Fn(&mut MicroWorker)) -> Json<RequestReceipt> {
+    if id != "Broadcast" && registry.read().get_worker(id).is_none() {
+        return Json(RequestReceipt {status: "Unavailable".to_string() });
+    }
+
+    let event = registry.events().record(EventKind::Command { target: id.to_string(), command: description.clone(), origin: caller.to_string() });
+    info!(command_id = event.id, target = id, origin = %caller, "New command: {}", description);
+    registry.metrics().commands.with_label_values(&[kind]).inc();
+
+    let command = |w: &mut MicroWorker| {
+        command(w);
+        w.command_id = event.id;
+    };
+
+    registry.update(|micro_manager| {
+        if id == "Broadcast" {
+            micro_manager.workers_mut().for_each(command);
+        } else if let Some(w) = micro_manager.get_worker_mut(id) {
+            command(w);
+        }
+    });
+
+    Json(RequestReceipt {status: "Complete".to_string() })
+}
+
+// Whole minutes from the portal, `None` for anything else
+fn parse_minutes(duration: &str) -> Option<Duration> {
+    u64::from_str(duration).ok()?.checked_mul(60).map(Duration::from_secs)
+}
+
+// Workers only carry the animations they were built with, and don't take '|' in a scene's name
+fn known_animation(animation: &str) -> bool {
+    render::ANIMATIONS.contains(&animation)
+}
+
+async fn message_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<MessageRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, message: {}", request.id, request.message);
+
+    let message = match state.content.sanitize(&request.message) {
+        Ok(m) => m,
+        Err(e) => {
+            info!(target = %request.id, "Rejected message: {}", e);
+            return Json(RequestReceipt {status: "Rejected".to_string() });
+        }
+    };
+
+    let message_cmd = MicroMessage { message };
+
+    let command = MicroCommand::Message(message_cmd);
+    assign(&state.registry, &caller, &request.id, command.kind(), command.describe(), |w| w.current_cmd = Some(command.clone()))
+}
+
+async fn timer_start_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<TimerRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, duration: {}", request.id, request.duration);
+
+    let Some(duration) = parse_minutes(&request.duration) else {
+        return Json(RequestReceipt {status: "Invalid".to_string() });
+    };
+    let timer_cmd = MicroTimer {start: tokio::time::Instant::now(), duration};
+
+    let command = MicroCommand::Timer(timer_cmd);
+    assign(&state.registry, &caller, &request.id, command.kind(), command.describe(), |w| w.current_cmd = Some(command.clone()))
+}
+
+async fn timer_add_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<TimerRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, duration: {}", request.id, request.duration);
+
+    let Some(duration) = parse_minutes(&request.duration) else {
+        return Json(RequestReceipt {status: "Invalid".to_string() });
+    };
+    let timer_cmd = MicroTimer {start: tokio::time::Instant::now(), duration};
+
+    assign(&state.registry, &caller, &request.id, "timer", format!("timer +{} min", timer_cmd.duration.as_secs() / 60), |w| {
+        if let Some(MicroCommand::Timer(ref mut existing_cmd)) = w.current_cmd {
+            existing_cmd.duration = existing_cmd.duration.saturating_add(timer_cmd.duration);
+        } else {
+            w.current_cmd = Some(MicroCommand::Timer(timer_cmd.clone()));
+        }
+    })
+}
+
+async fn animation_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<AnimationRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, animation: {}", request.id, request.animation);
+
+    if !known_animation(&request.animation) {
+        return Json(RequestReceipt {status: "Invalid".to_string() });
+    }
+    let animation_cmd = MicroAnimation {animation: request.animation};
+
+    let command = MicroCommand::Animation(animation_cmd);
+    assign(&state.registry, &caller, &request.id, command.kind(), command.describe(), |w| w.current_cmd = Some(command.clone()))
+}
+
+async fn scene_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<SceneRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, animation: {}, duration: {}, message: {}", request.id, request.animation, request.duration, request.message);
+
+    // Both layers are optional, a scene may be nothing but text
+    if !request.animation.is_empty() && !known_animation(&request.animation) {
+        return Json(RequestReceipt {status: "Invalid".to_string() });
+    }
+
+    let timer = if request.duration.is_empty() {
+        None
+    } else {
+        match parse_minutes(&request.duration) {
+            Some(duration) => Some(MicroTimer {start: tokio::time::Instant::now(), duration}),
+            None => return Json(RequestReceipt {status: "Invalid".to_string() }),
+        }
+    };
+
+    // The overlay text is optional, only text that is there has to pass
+    let message = if request.message.is_empty() {
+        request.message
+    } else {
+        match state.content.sanitize(&request.message) {
+            Ok(m) => m,
+            Err(e) => {
+                info!(target = %request.id, "Rejected scene message: {}", e);
+                return Json(RequestReceipt {status: "Rejected".to_string() });
+            }
+        }
+    };
+
+    let scene_cmd = MicroScene {animation: request.animation, timer, message};
+
+    let command = MicroCommand::Scene(scene_cmd);
+    assign(&state.registry, &caller, &request.id, command.kind(), command.describe(), |w| w.current_cmd = Some(command.clone()))
+}
+
+/// Set or, with an empty key, clear the registration key of a worker.
+async fn registry_key_handler(State(state): State<Arc<AppState>>, extract::Json(request): extract::Json<KeyRequest>) -> Json<RequestReceipt> {
+
+    info!("id: {}, registration key {}", request.id, if request.key.is_empty() {"cleared"} else {"set"});
+
+    if request.key.is_empty() {
+        state.registry.update(|m| m.remove_key(&request.id));
+        Json(RequestReceipt {status: "Complete".to_string() })
+    } else if let Some(key) = registration::parse_key(&request.key) {
+        state.registry.update(|m| m.set_key(&request.id, key));
+        Json(RequestReceipt {status: "Complete".to_string() })
+    } else {
+        Json(RequestReceipt {status: "Invalid".to_string() })
+    }
+}
+
+async fn registry_remove_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<RemoveRequest>) -> Json<RequestReceipt> {
+
+    info!(origin = %caller, "id: {}, removing from registry", request.id);
+
+    if state.registry.read().get_worker(&request.id).is_some() {
+        state.registry.update(|m| m.remove_worker(&request.id));
+        state.registry.worker_logs().forget(&request.id);
+        state.registry.events().record(EventKind::Removed { worker: request.id, reason: format!("removed by {}", caller) });
+        Json(RequestReceipt {status: "Complete".to_string() })
+    } else {
+        Json(RequestReceipt {status: "Unavailable".to_string() })
+    }
+}
+
+/// Images workers can be updated to, by version.
+async fn firmware_list_handler(State(state): State<Arc<AppState>>) -> Json<Vec<ImageInfo>> {
+    Json(state.registry.firmware().list())
+}
+
+/// The image of `version` for workers to download, see [`firmware`].
+async fn firmware_download_handler(State(state): State<Arc<AppState>>, extract::Path(version): extract::Path<String>) -> axum::response::Response {
+    match state.registry.firmware().get(&version) {
+        Some(image) => ([(header::CONTENT_TYPE, "application/octet-stream")], image.data.to_vec()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Take the image in the body as `version`, signed with `signature` if given.
+async fn firmware_upload_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Query(query): extract::Query<UploadQuery>, body: Bytes) -> Json<RequestReceipt> {
+
+    let signature = match query.signature.as_deref().filter(|s| !s.is_empty()) {
+        Some(hex) => match ota::parse_hex(hex) {
+            Some(signature) => Some(signature),
+            None => return Json(RequestReceipt {status: "Invalid".to_string() }),
+        },
+        None => None,
+    };
+
+    let status = match state.registry.firmware().add(&query.version, body.to_vec(), signature) {
+        Ok(image) => {
+            info!(origin = %caller, "Firmware {} uploaded, {} bytes, sha256 {}", image.version, image.size, image.sha256);
+            "Complete"
+        },
+        Err(UploadError::InvalidVersion | UploadError::TooLarge) => "Invalid",
+        Err(e @ (UploadError::Unsigned | UploadError::Signature)) => {
+            warn!(origin = %caller, "Rejected firmware {}: {:?}", query.version, e);
+            "Rejected"
+        },
+        Err(UploadError::Storage(e)) => {
+            error!("Unable to keep firmware {}: {}", query.version, e);
+            "Failed"
+        },
+    };
+    Json(RequestReceipt {status: status.to_string() })
+}
+
+/// Update `id`, or every worker for "Broadcast", to the uploaded image of `version`. Workers that
+/// report running it already are left alone, `id` being one is answered with "Current".
+async fn ota_handler(State(state): State<Arc<AppState>>, Extension(caller): Extension<Caller>, extract::Json(request): extract::Json<OtaRequest>) -> Json<RequestReceipt> {
+
+    debug!("id: {}, version: {}", request.id, request.version);
+
+    let Some(image) = state.registry.firmware().get(&request.version) else {
+        return Json(RequestReceipt {status: "Unavailable".to_string() });
+    };
+
+    let ota_cmd = MicroOta {
+        version: image.version.clone(),
+        size: image.size(),
+        sha256: image.sha256,
+        signature: image.signature,
+        location: state.firmware_location.clone(),
+    };
+
+    let running = |w: &MicroWorker| w.telemetry.as_ref().and_then(|t| t.firmware.as_deref()) == Some(image.version.as_str());
+    if state.registry.read().get_worker(&request.id).is_some_and(running) {
+        info!(target = %request.id, origin = %caller, "Already running {}, no update sent", image.version);
+        return Json(RequestReceipt {status: "Current".to_string() });
+    }
+
+    let command = MicroCommand::Ota(ota_cmd.clone());
+    assign(&state.registry, &caller, &request.id, command.kind(), command.describe(), |w| {
+        if !running(w) {
+            w.update = Some(ota_cmd.clone());
+            w.updating_to = None;
+        }
+    })
+}
+
+/// Routes for the portal and the command API.
+pub fn app(registry: Registry, server_config: &ServerConfig) -> Router {
+
+    let metrics = registry.metrics().clone();
+    let firmware_location = FirmwareLocation {
+        base_url: server_config.firmware_url.clone(),
+        port: server_config.http_addr.port(),
+        https: server_config.tls_cert.is_some(),
+    };
+    let shared_state = Arc::new(AppState { registry, content: server_config.content.clone(), firmware_location });
+    let access = Arc::new(server_config.access.clone());
+    let limiter = Arc::new(RateLimiter::new(server_config.rate_limits.clone()));
+
+    if access.is_open() {
+        warn!("No users or tokens configured, the portal and API are open to everyone");
+    }
+
+    let viewer = Router::new()
+        .route("/", get(portal_handler))
+        .route("/api/workers", get(workers_handler))
+        .route("/api/workers/:mac/logs", get(worker_logs_handler))
+        .route("/api/events", get(events_handler))
+        .route("/api/firmware", get(firmware_list_handler))
+        .route("/metrics", get(metrics_handler))
+        .route_layer(middleware::from_fn_with_state((access.clone(), Role::Viewer), auth::authorize));
+
+    let operator = Router::new().route("/messaging", post(message_handler))
+        .route("/timerStart", post(timer_start_handler))
+        .route("/timerAdd", post(timer_add_handler))
+        .route("/animation", post(animation_handler))
+        .route("/scene", post(scene_handler))
+        .route_layer(middleware::from_fn_with_state(limiter, limits::limit))
+        .route_layer(middleware::from_fn_with_state((access.clone(), Role::Operator), auth::authorize));
+
+    let admin = Router::new()
+        .route("/registry/key", post(registry_key_handler))
+        .route("/registry/remove", post(registry_remove_handler))
+        .route("/firmware", post(firmware_upload_handler).layer(DefaultBodyLimit::max(firmware::MAX_IMAGE_SIZE)))
+        .route("/ota", post(ota_handler))
+        .route_layer(middleware::from_fn_with_state((access.clone(), Role::Admin), auth::authorize));
+
+    // Workers fetching images have no credentials
+    let public = Router::new()
+        .route("/firmware/:version", get(firmware_download_handler));
+
+    viewer.merge(operator).merge(admin).merge(public).with_state(shared_state)
+        .layer(middleware::from_fn_with_state(metrics, metrics::count_requests))
+}
+
+/// Serve `app` over HTTPS on `listener` with the PEM certificate chain and private key at the
+/// given paths.
+pub async fn serve_tls(listener: std::net::TcpListener, app: Router, cert: &Path, key: &Path) -> std::io::Result<()> {
+    // Another provider may already be installed, either one will do
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let tls_config = RustlsConfig::from_pem_file(cert, key).await?;
+
+    listener.set_nonblocking(true)?;
+    axum_server::from_tcp_rustls(listener, tls_config).serve(app.into_make_service_with_connect_info::<SocketAddr>()).await
+}
+
+/// Accept worker registrations on `registration_channel` forever. Workers that don't name a port
+/// are sent directives on `worker_port`. Workers with a key in the registry have to answer a
+/// challenge, those without are only accepted with `open_registration`.
+pub async fn registration_loop(registry: Registry, registration_channel: tokio::net::TcpListener, worker_port: u16, open_registration: bool) {
+
+    loop {
+        trace!("Checking Registration Requests");
+
+        match registration_channel.accept().await {
+            Ok((socket, peer)) => {
+                let span = info_span!("registration", %peer, worker = tracing::field::Empty);
+                tokio::spawn(register_worker(registry.clone(), socket, worker_port, open_registration).instrument(span));
+            },
+            Err(error) => error!("Connection failed: {}", error),
+        };
+    }
+}
+
+/// Answer discovery queries from workers on `socket` forever, pointing them at `registration_port`
+/// on this host.
+pub async fn discovery_loop(socket: tokio::net::UdpSocket, registration_port: u16) {
+
+    let mut buffer = [0u8; 64];
+
+    loop {
+        match socket.recv_from(&mut buffer).await {
+            Ok((n, from)) => {
+                if let Some(reply) = discovery::answer(&buffer[..n], registration_port) {
+                    debug!("Answering discovery request from {}", from);
+                    if let Err(e) = socket.send_to(&reply, from).await {
+                        warn!("Discovery reply failed: {}", e);
+                    }
+                }
+            },
+            Err(e) => error!("Discovery failed: {}", e),
+        }
+    }
+}
+
+/// Send every worker its current command (or a ping) once per `sweep_interval`, forever. Whether
+/// it gets through decides the worker's liveness under `liveness_policy`. Changes to the registry,
+/// like a new command, start a sweep right away.
+///
+/// Each worker is contacted from its own task, so one that is slow or unreachable only holds up
+/// itself. A worker still busy with the previous sweep is skipped, its task goes again when
+/// the registry changed in the meantime.
+pub async fn broadcast_loop(registry: Registry, sweep_interval: Duration, connect_timeout: Duration, liveness_policy: LivenessPolicy) {
+
+    let in_flight: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    let mut changes = registry.subscribe();
+
+    loop {
+
+        let revision = *changes.borrow_and_update();
+        let workers: Vec<MicroWorker>;
+        {
+            let in_flight = in_flight.lock().unwrap();
+            workers = registry.read().workers()
+                .filter(|w| w.ip_address.is_some() && !in_flight.contains(&w.mac_address))
+                .cloned()
+                .collect();
+        }
+
+        trace!("Managing {} worker(s)", workers.len());
+        for worker in workers
+        {
+            in_flight.lock().unwrap().insert(worker.mac_address.clone());
+
+            let registry = registry.clone();
+            let in_flight = in_flight.clone();
+            let liveness_policy = liveness_policy.clone();
+            tokio::spawn(async move {
+                let mut worker = worker;
+                let mut revision = revision;
+                loop {
+                    let span = info_span!("directive", worker = %worker.mac_address, command_id = worker.command_id);
+                    send_directive(&registry, &worker, connect_timeout, &liveness_policy).instrument(span).await;
+
+                    // Checked under the lock, so a change either finds the worker idle or is seen here
+                    let mut in_flight = in_flight.lock().unwrap();
+                    let latest = registry.revision();
+                    match registry.read().get_worker(&worker.mac_address) {
+                        Some(w) if latest != revision && w.ip_address.is_some() => {
+                            worker = w.clone();
+                            revision = latest;
+                        },
+                        _ => {
+                            in_flight.remove(&worker.mac_address);
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+
+        for mac_address in registry.record(|m| m.expire_workers(&liveness_policy)) {
+            registry.worker_logs().forget(&mac_address);
+            registry.events().record(EventKind::Removed { worker: mac_address, reason: format!("offline for {} s", liveness_policy.retention.as_secs()) });
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(sweep_interval) => {},
+            _ = changes.changed() => {},
+        }
+    }
+}
+
+// One sweep for one worker: deliver its directive, collect any report and note whether it answered
+async fn send_directive(registry: &Registry, worker: &MicroWorker, connect_timeout: Duration, liveness_policy: &LivenessPolicy) {
+
+    let Some(ip_address) = worker.ip_address else { return };
+    let metrics = registry.metrics();
+
+    // A pending update goes out once, ahead of the current command
+    let command = worker.update.clone().map(MicroCommand::Ota)
+        .or_else(|| worker.current_cmd.clone())
+        .unwrap_or(MicroCommand::Ping(MicroPing{}));
+
+    let connecting = Instant::now();
+    match timeout(connect_timeout, tokio::net::TcpStream::connect(ip_address)).await {
+        Ok(stream_s) => {
+            match stream_s {
+                Ok(mut stream) => {
+                    metrics.connect_latency.observe(connecting.elapsed().as_secs_f64());
+                    match command.execute(&mut stream, worker.session.as_deref()).await {
+                        Ok(()) => {
+                            metrics.directives_sent.with_label_values(&[command.kind()]).inc();
+                            note_delivery(registry, worker, &command, Ok(()));
+                            if let MicroCommand::Ota(update) = &command {
+                                registry.record(|m| m.update_sent(&worker.mac_address, &update.version));
+                            }
+                            if registry.record(|m| m.heard_from(&worker.mac_address)) {
+                                registry.events().record(EventKind::Liveness { worker: worker.mac_address.clone(), liveness: Liveness::Online });
+                            }
+                            if let Some(telemetry) = read_reply(registry, &worker.mac_address, worker.session.as_deref(), stream, connect_timeout).await {
+                                note_telemetry(registry, &worker.mac_address, telemetry, false);
+                            }
+                        },
+                        Err(e) => {
+                            debug!("write to {} failed: {}", worker.name(), e);
+                            metrics.delivery_failures.with_label_values(&["write"]).inc();
+                            note_delivery(registry, worker, &command, Err(e.to_string()));
+                            missed_sweep(registry, worker, liveness_policy);
+                        }
+                    };
+                },
+
+                Err(e) => {
+                    debug!("connect to {} failed: {}", worker.name(), e);
+                    metrics.delivery_failures.with_label_values(&["connect"]).inc();
+                    note_delivery(registry, worker, &command, Err(e.to_string()));
+                    missed_sweep(registry, worker, liveness_policy);
+                }
+            }
+        },
+        Err(e) => {
+            debug!("connect to {} timed out: {}", worker.name(), e);
+            metrics.delivery_failures.with_label_values(&["timeout"]).inc();
+            note_delivery(registry, worker, &command, Err("timed out".to_string()));
+            missed_sweep(registry, worker, liveness_policy);
+        }
+    }
+}
+
+fn missed_sweep(registry: &Registry, worker: &MicroWorker, liveness_policy: &LivenessPolicy) {
+    if let Some(liveness) = registry.record(|m| m.missed_sweep(&worker.mac_address, liveness_policy)) {
+        registry.events().record(EventKind::Liveness { worker: worker.mac_address.clone(), liveness });
+    }
+}
+
+// Log whether the command a worker was given got to it, once for each outcome
+fn note_delivery(registry: &Registry, worker: &MicroWorker, command: &MicroCommand, result: Result<(), String>) {
+    if let MicroCommand::Ping(_) = command {
+        return;
+    }
+
+    if !registry.record(|m| m.delivery(&worker.mac_address, worker.command_id, result.is_ok())) {
+        return;
+    }
+    match &result {
+        Ok(()) => info!("Delivered {}", command.describe()),
+        Err(error) => warn!("Failed to deliver {}: {}", command.describe(), error),
+    }
+
+    let worker = worker.mac_address.clone();
+    let command = command.describe();
+    registry.events().record(match result {
+        Ok(()) => EventKind::Delivered { worker, command },
+        Err(error) => EventKind::DeliveryFailed { worker, command, error },
+    });
+}
@@ -0,0 +1,200 @@
+//! How fast commands may be sent.
+//!
+//! Every client, told apart by IP address, gets a token bucket: `burst` commands in a row,
+//! refilled at `per_minute`. On top of that a worker keeps each command for at least `cooldown`
+//! before the next one may replace it, a broadcast counting as a command to every worker. A
+//! command holds its target's cooldown from the moment it is let through, so commands sent at
+//! once can't all slip past, and gives it back if the handler turns it down.
+//! Requests over either limit are answered with 429 Too Many Requests, a `Retry-After` header and
+//! a `RateLimited` receipt, before the handler ever locks the [`crate::MicroManager`].
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use axum::body::{self, Body};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use serde::Deserialize;
+
+use tokio::time::{Duration, Instant};
+
+// Command requests are a few hundred bytes of JSON
+const BODY_LIMIT: usize = 64 * 1024;
+
+// Commands stay on screen for at least a sweep at the default interval
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(1);
+
+// Clients with a full bucket are forgotten once there are this many
+const MAX_CLIENTS: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct RateLimits {
+    /// Commands a client may send per minute, 0 for no limit.
+    pub per_minute: u32,
+    /// Commands a client may send in a row before being held to `per_minute`.
+    pub burst: u32,
+    /// Least time between two commands to the same worker.
+    pub cooldown: Duration,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits { per_minute: 120, burst: 20, cooldown: DEFAULT_COOLDOWN }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+pub struct RateLimiter {
+    limits: RateLimits,
+    clients: Mutex<HashMap<IpAddr, Bucket>>,
+    // Last command by target id, "Broadcast" included
+    targets: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+
+    pub fn new(limits: RateLimits) -> Self {
+        RateLimiter { limits, clients: Mutex::new(HashMap::new()), targets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Take a token from `client`'s bucket, or return how long until there is one.
+    pub fn check_client(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.limits.per_minute == 0 {
+            return Ok(());
+        }
+
+        let rate = self.limits.per_minute as f64 / 60.0;
+        let burst = self.limits.burst.max(1) as f64;
+
+        let mut clients = self.clients.lock().unwrap();
+
+        if clients.len() >= MAX_CLIENTS {
+            clients.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst);
+        }
+
+        let bucket = clients.entry(client).or_insert(Bucket { tokens: burst, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+
+    /// Start the cooldown of the worker `id`, or `Broadcast`, for a command at `now` if it may go
+    /// out, or return how long is left of the cooldown. What the cooldown started from before is
+    /// returned for [`RateLimiter::release_target`].
+    pub fn reserve_target(&self, id: &str, now: Instant) -> Result<Option<Instant>, Duration> {
+        if self.limits.cooldown.is_zero() {
+            return Ok(None);
+        }
+
+        let mut targets = self.targets.lock().unwrap();
+
+        // A broadcast has to wait for every worker, a worker for itself and the last broadcast
+        let last = if id == "Broadcast" {
+            targets.values().max().copied()
+        } else {
+            [targets.get(id), targets.get("Broadcast")].into_iter().flatten().max().copied()
+        };
+
+        match last {
+            Some(last) if now.duration_since(last) < self.limits.cooldown => Err(self.limits.cooldown - now.duration_since(last)),
+            _ => Ok(targets.insert(id.to_string(), now)),
+        }
+    }
+
+    /// Put the cooldown of `id` back to `previous` after its command reserved at `now` didn't go
+    /// out, unless a later command has taken it since.
+    pub fn release_target(&self, id: &str, now: Instant, previous: Option<Instant>) {
+        if self.limits.cooldown.is_zero() {
+            return;
+        }
+
+        let mut targets = self.targets.lock().unwrap();
+        if targets.get(id) != Some(&now) {
+            return;
+        }
+        match previous {
+            Some(previous) => targets.insert(id.to_string(), previous),
+            None => targets.remove(id),
+        };
+    }
+}
+
+#[derive(Deserialize)]
+struct Target {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Receipt {
+    status: String,
+}
+
+/// Middleware holding command requests to the client and worker limits.
+pub async fn limit(State(limiter): State<Arc<RateLimiter>>, request: Request, next: Next) -> Response {
+    let now = Instant::now();
+
+    // Without connection info every client shares one bucket
+    let client = request.extensions().get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+    if let Err(wait) = limiter.check_client(client, now) {
+        tracing::warn!(method = %request.method(), uri = %request.uri(), %client, "Rate limited");
+        return too_many_requests(wait);
+    }
+
+    // The target is in the body, which has to be put back for the handler
+    let (parts, body) = request.into_parts();
+    let bytes = match body::to_bytes(body, BODY_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+
+    // Malformed requests are left to the handler to turn down
+    let reserved = match serde_json::from_slice::<Target>(&bytes) {
+        Ok(target) => match limiter.reserve_target(&target.id, now) {
+            Ok(previous) => Some((target.id, previous)),
+            Err(wait) => {
+                tracing::info!(worker = %target.id, wait_ms = wait.as_millis() as u64, "Cooling down");
+                return too_many_requests(wait);
+            }
+        },
+        Err(_) => None,
+    };
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    let Some((id, previous)) = reserved else { return response };
+
+    // The receipt tells whether the command went out
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = body::to_bytes(body, BODY_LIMIT).await else {
+        limiter.release_target(&id, now, previous);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    if !serde_json::from_slice::<Receipt>(&bytes).is_ok_and(|r| r.status == "Complete") {
+        limiter.release_target(&id, now, previous);
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    // Whole seconds, rounded up so a retry isn't early
+    let seconds = wait.as_millis().div_ceil(1000).max(1);
+    (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, seconds.to_string())], Json(serde_json::json!({ "status": "RateLimited" }))).into_response()
+}
@@ -0,0 +1,97 @@
+//! Whether workers are still there.
+//!
+//! Every sweep of the broadcast loop doubles as a heartbeat: a worker that takes its directive is
+//! online. Sweeps it misses in a row are counted, after `degraded_after` of them it is degraded and
+//! after `offline_after` offline. Offline workers are still tried on every sweep, and they are
+//! back online with their current command as soon as one gets through or they register again.
+//! Workers that aren't persistent are only forgotten after being offline for `retention`, so a
+//! WiFi blip doesn't lose what they were showing.
+
+use serde::{Deserialize, Serialize};
+
+use tokio::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Liveness {
+    Online,
+    Degraded,
+    Offline,
+}
+
+impl Liveness {
+
+    /// As in the API, `online`, `degraded` or `offline`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Liveness::Online => "online",
+            Liveness::Degraded => "degraded",
+            Liveness::Offline => "offline",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LivenessPolicy {
+    /// Missed sweeps in a row before a worker is degraded.
+    pub degraded_after: u32,
+    /// Missed sweeps in a row before a worker is offline.
+    pub offline_after: u32,
+    /// How long a worker that isn't persistent is kept while offline.
+    pub retention: Duration,
+}
+
+impl Default for LivenessPolicy {
+    fn default() -> Self {
+        LivenessPolicy { degraded_after: 1, offline_after: 3, retention: Duration::from_secs(300) }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub liveness: Liveness,
+    /// When the worker last registered or took a directive.
+    pub last_seen: Option<Instant>,
+    /// Sweeps missed since then.
+    pub missed: u32,
+    offline_since: Option<Instant>,
+}
+
+impl Heartbeat {
+
+    /// A worker that hasn't been heard from yet.
+    pub fn new() -> Self {
+        Heartbeat { liveness: Liveness::Offline, last_seen: None, missed: 0, offline_since: None }
+    }
+
+    pub fn seen(&mut self, now: Instant) {
+        self.liveness = Liveness::Online;
+        self.last_seen = Some(now);
+        self.missed = 0;
+        self.offline_since = None;
+    }
+
+    pub fn missed(&mut self, policy: &LivenessPolicy, now: Instant) {
+        self.missed = self.missed.saturating_add(1);
+
+        if self.missed >= policy.offline_after.max(1) {
+            if self.liveness != Liveness::Offline {
+                self.offline_since = Some(now);
+            }
+            self.liveness = Liveness::Offline;
+        } else if self.missed >= policy.degraded_after {
+            self.liveness = Liveness::Degraded;
+        }
+    }
+
+    /// Whether the worker has been offline for longer than the policy keeps workers around.
+    pub fn expired(&self, policy: &LivenessPolicy, now: Instant) -> bool {
+        self.offline_since.is_some_and(|since| now.duration_since(since) >= policy.retention)
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,62 @@
+//! What the server logs, and how.
+//!
+//! Logging goes through `tracing`. A registration runs in a `registration` span with the peer
+//! address and, once known, the worker's MAC address. Each delivery runs in a `directive` span
+//! with the MAC address and the command id, the id of the event that logged the command, so a
+//! line can be traced back to who gave the command. Lines workers forward after a directive are
+//! logged in its span with the target `worker`.
+//!
+//! Which lines are kept is an [`EnvFilter`] directive such as `info` or `server=debug,tower=warn`,
+//! `RUST_LOG` wins over the setting when set. Lines are written as text or, for log collectors,
+//! as one JSON object each.
+
+use std::str::FromStr;
+
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err("expected text or json".to_string()),
+        }
+    }
+}
+
+/// Install the global subscriber, call once before anything is logged.
+pub fn init(filter: &str, format: LogFormat) -> Result<(), String> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(&directives).map_err(|e| format!("{}: {}", EnvFilter::DEFAULT_ENV, e))?,
+        _ => EnvFilter::try_new(filter).map_err(|e| format!("Invalid log filter '{}': {}", filter, e))?,
+    };
+
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let installed = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+    };
+    installed.map_err(|e| e.to_string())
+}
+
+/// Log a line a worker forwarded, at the level it was logged at.
+pub fn forwarded(level: &str, message: &str) {
+    match level {
+        "error" => tracing::error!(target: "worker", "{}", message),
+        "warn"  => tracing::warn!(target: "worker", "{}", message),
+        "debug" => tracing::debug!(target: "worker", "{}", message),
+        "trace" => tracing::trace!(target: "worker", "{}", message),
+        _       => tracing::info!(target: "worker", "{}", message),
+    }
+}
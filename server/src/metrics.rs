@@ -0,0 +1,103 @@
+//! Fleet health for Prometheus.
+//!
+//! `/metrics` answers in the Prometheus text format, to viewers like the rest of the read-only
+//! API, so scrapers need a viewer token when users or tokens are configured:
+//!
+//! ```text
+//! mb_workers{liveness}                          workers online, degraded and offline
+//! mb_commands_total{type}                       commands given through the API
+//! mb_directives_sent_total{type}                directives that reached a worker, pings included
+//! mb_delivery_failures_total{reason}            directives that didn't, by connect, timeout or write
+//! mb_worker_connect_seconds                     how long workers took to accept a connection
+//! mb_registration_attempts_total{outcome}       registrations accepted, rejected or invalid
+//! mb_http_requests_total{method,route,status}   requests to the portal and API
+//! mb_firmware_updates_total{outcome}            firmware updates workers came back from, updated or failed
+//! ```
+//!
+//! Counters are kept as things happen, the worker gauge is taken from the registry on each scrape.
+
+use std::sync::Arc;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, IntGaugeVec, Opts, TextEncoder};
+
+use crate::liveness::Liveness;
+use crate::MicroManager;
+
+// Workers are on the local network, connecting takes milliseconds unless something is wrong
+const CONNECT_BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+pub struct Metrics {
+    registry: prometheus::Registry,
+    workers: IntGaugeVec,
+    pub commands: IntCounterVec,
+    pub directives_sent: IntCounterVec,
+    pub delivery_failures: IntCounterVec,
+    pub connect_latency: Histogram,
+    pub registrations: IntCounterVec,
+    pub http_requests: IntCounterVec,
+    pub updates: IntCounterVec,
+}
+
+impl Metrics {
+
+    pub fn new() -> Self {
+        let workers = IntGaugeVec::new(Opts::new("mb_workers", "Workers by liveness"), &["liveness"]).unwrap();
+        let commands = IntCounterVec::new(Opts::new("mb_commands_total", "Commands given through the API"), &["type"]).unwrap();
+        let directives_sent = IntCounterVec::new(Opts::new("mb_directives_sent_total", "Directives that reached a worker"), &["type"]).unwrap();
+        let delivery_failures = IntCounterVec::new(Opts::new("mb_delivery_failures_total", "Directives that didn't reach a worker"), &["reason"]).unwrap();
+        let connect_latency = Histogram::with_opts(HistogramOpts::new("mb_worker_connect_seconds", "Time for a worker to accept a connection").buckets(CONNECT_BUCKETS.to_vec())).unwrap();
+        let registrations = IntCounterVec::new(Opts::new("mb_registration_attempts_total", "Registration attempts by outcome"), &["outcome"]).unwrap();
+        let http_requests = IntCounterVec::new(Opts::new("mb_http_requests_total", "Requests to the portal and API"), &["method", "route", "status"]).unwrap();
+        let updates = IntCounterVec::new(Opts::new("mb_firmware_updates_total", "Firmware updates by outcome"), &["outcome"]).unwrap();
+
+        let registry = prometheus::Registry::new();
+        registry.register(Box::new(workers.clone())).unwrap();
+        registry.register(Box::new(commands.clone())).unwrap();
+        registry.register(Box::new(directives_sent.clone())).unwrap();
+        registry.register(Box::new(delivery_failures.clone())).unwrap();
+        registry.register(Box::new(connect_latency.clone())).unwrap();
+        registry.register(Box::new(registrations.clone())).unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(updates.clone())).unwrap();
+
+        Metrics { registry, workers, commands, directives_sent, delivery_failures, connect_latency, registrations, http_requests, updates }
+    }
+
+    /// Everything in the text format, with the worker counts of `micro_manager`.
+    pub fn render(&self, micro_manager: &MicroManager) -> String {
+        for liveness in [Liveness::Online, Liveness::Degraded, Liveness::Offline] {
+            let count = micro_manager.workers().filter(|w| w.heartbeat.liveness == liveness).count();
+            self.workers.with_label_values(&[liveness.label()]).set(count as i64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware counting requests by method, route and status. Requests no route matched are
+/// counted as `unmatched` rather than by path, which anyone could make up.
+pub async fn count_requests(State(metrics): State<Arc<Metrics>>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|p| p.as_str().to_string()).unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+    metrics.http_requests.with_label_values(&[&method, &route, response.status().as_str()]).inc();
+    response
+}
+
+pub fn respond(body: String) -> Response {
+    ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body).into_response()
+}
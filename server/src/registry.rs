@@ -0,0 +1,105 @@
+//! The worker registry shared by the portal, the registration listener and the broadcast loop.
+//!
+//! A [`Registry`] is a cheap handle on one [`MicroManager`] behind a read/write lock, so lookups
+//! from handlers don't wait on each other. Changes go through [`Registry::update`], which bumps a
+//! revision on a watch channel: subscribers learn that something changed without polling. The
+//! broadcast loop is one of them, new commands go out right away instead of on the next sweep.
+//! What sweeps learn about workers, liveness and telemetry, is kept with [`Registry::record`],
+//! which doesn't notify.
+//!
+//! The registry also carries the [`EventLog`], so whoever changes it can say what happened, the
+//! [`Metrics`] counting it, the [`WorkerLogs`] workers forward and the [`FirmwareStore`] they are
+//! updated from.
+//!
+//! The lock is never held across an `.await`.
+
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+use tokio::sync::watch;
+
+use crate::events::EventLog;
+use crate::firmware::FirmwareStore;
+use crate::metrics::Metrics;
+use crate::worker_logs::WorkerLogs;
+use crate::MicroManager;
+
+#[derive(Clone)]
+pub struct Registry {
+    manager: Arc<RwLock<MicroManager>>,
+    revision: Arc<watch::Sender<u64>>,
+    events: Arc<EventLog>,
+    metrics: Arc<Metrics>,
+    worker_logs: Arc<WorkerLogs>,
+    firmware: Arc<FirmwareStore>,
+}
+
+impl Registry {
+
+    /// A registry keeping its events in memory only.
+    pub fn new(manager: MicroManager) -> Self {
+        Self::with_events(manager, EventLog::in_memory())
+    }
+
+    pub fn with_events(manager: MicroManager, events: EventLog) -> Self {
+        let (revision, _) = watch::channel(0);
+        Registry { manager: Arc::new(RwLock::new(manager)), revision: Arc::new(revision), events: Arc::new(events), metrics: Arc::new(Metrics::new()), worker_logs: Arc::new(WorkerLogs::default()), firmware: Arc::new(FirmwareStore::default()) }
+    }
+
+    /// The same registry keeping the lines workers forward in `worker_logs`.
+    pub fn with_worker_logs(self, worker_logs: WorkerLogs) -> Self {
+        Registry { worker_logs: Arc::new(worker_logs), ..self }
+    }
+
+    /// The same registry updating workers from the images in `firmware`.
+    pub fn with_firmware(self, firmware: FirmwareStore) -> Self {
+        Registry { firmware: Arc::new(firmware), ..self }
+    }
+
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn worker_logs(&self) -> &WorkerLogs {
+        &self.worker_logs
+    }
+
+    pub fn firmware(&self) -> &FirmwareStore {
+        &self.firmware
+    }
+
+    /// Shared access for lookups, don't keep it for long.
+    pub fn read(&self) -> RwLockReadGuard<'_, MicroManager> {
+        self.manager.read().unwrap()
+    }
+
+    /// Change the registry and let subscribers know.
+    pub fn update<R>(&self, change: impl FnOnce(&mut MicroManager) -> R) -> R {
+        let result = change(&mut self.manager.write().unwrap());
+        self.revision.send_modify(|r| *r += 1);
+        result
+    }
+
+    /// Change the registry without notifying, for bookkeeping nobody needs to act on.
+    pub fn record<R>(&self, change: impl FnOnce(&mut MicroManager) -> R) -> R {
+        change(&mut self.manager.write().unwrap())
+    }
+
+    /// Revisions of the registry, the receiver sees a new one after every [`Registry::update`].
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.revision.subscribe()
+    }
+
+    pub fn revision(&self) -> u64 {
+        *self.revision.borrow()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(MicroManager::new())
+    }
+}
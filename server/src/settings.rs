@@ -0,0 +1,346 @@
+//! Runtime configuration of the server.
+//!
+//! Every setting has a default and can be overridden, in increasing order of precedence, by a
+//! TOML file (`--config <path>` or `MB_CONFIG`), an `MB_*` environment variable and a command
+//! line flag. The same keys are used everywhere:
+//!
+//! ```text
+//! file key               environment                flag
+//! http_addr              MB_HTTP_ADDR               --http-addr
+//! registration_addr      MB_REGISTRATION_ADDR       --registration-addr
+//! discovery_addr         MB_DISCOVERY_ADDR          --discovery-addr
+//! worker_port            MB_WORKER_PORT             --worker-port
+//! sweep_interval_ms      MB_SWEEP_INTERVAL_MS       --sweep-interval-ms
+//! connect_timeout_ms     MB_CONNECT_TIMEOUT_MS      --connect-timeout-ms
+//! open_registration      MB_OPEN_REGISTRATION       --open-registration
+//! tls_cert               MB_TLS_CERT                --tls-cert
+//! tls_key                MB_TLS_KEY                 --tls-key
+//! max_message_len        MB_MAX_MESSAGE_LEN         --max-message-len
+//! rate_limit_per_minute  MB_RATE_LIMIT_PER_MINUTE   --rate-limit-per-minute
+//! rate_limit_burst       MB_RATE_LIMIT_BURST        --rate-limit-burst
+//! worker_cooldown_ms     MB_WORKER_COOLDOWN_MS      --worker-cooldown-ms
+//! degraded_after_misses  MB_DEGRADED_AFTER_MISSES   --degraded-after-misses
+//! offline_after_misses   MB_OFFLINE_AFTER_MISSES    --offline-after-misses
+//! worker_retention_ms    MB_WORKER_RETENTION_MS     --worker-retention-ms
+//! event_log              MB_EVENT_LOG               --event-log
+//! log_filter             MB_LOG_FILTER              --log-filter
+//! log_format             MB_LOG_FORMAT              --log-format
+//! worker_log_lines       MB_WORKER_LOG_LINES        --worker-log-lines
+//! firmware_dir           MB_FIRMWARE_DIR            --firmware-dir
+//! firmware_url           MB_FIRMWARE_URL            --firmware-url
+//! firmware_public_key    MB_FIRMWARE_PUBLIC_KEY     --firmware-public-key
+//! ```
+//!
+//! Only workers holding a key in `worker_keys` can register unless `open_registration` is set,
+//! which `--open-registration` on its own does as well as `--open-registration true`.
+//! The portal is served over HTTPS when both `tls_cert` and `tls_key` name PEM files. An empty
+//! `event_log` keeps events in memory only. `log_filter` and `log_format` (`text` or `json`) are
+//! described in [`crate::logging`]. `worker_log_lines` is how many of the lines each worker
+//! forwards are kept, 0 keeps none. An empty `firmware_dir` keeps uploaded images in memory only,
+//! `firmware_url` and `firmware_public_key` (hex, from `ota-sign public`) are described in
+//! [`crate::firmware`].
+//!
+//! Registration keys can only be given in the file, as hex by MAC address:
+//!
+//! ```text
+//! [worker_keys]
+//! "EC:DA:3B:BF:46:9C" = "5f1c0e8a2b7d4e6f9a3c1b0d8e7f6a5b"
+//! ```
+//!
+//! So can portal users and API tokens, see [`crate::auth`] for the roles:
+//!
+//! ```text
+//! [users]
+//! teacher = { password = "correct horse", role = "admin" }
+//! helper = { password = "battery staple", role = "operator" }
+//!
+//! [tokens]
+//! "3b9f6c0d2e" = "viewer"
+//! ```
+//!
+//! As can words to mask in messages:
+//!
+//! ```text
+//! blocklist = ["darn", "heck"]
+//! ```
+
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::auth::{AccessControl, Role};
+use crate::content::ContentPolicy;
+use crate::limits::RateLimits;
+use crate::liveness::LivenessPolicy;
+use crate::logging::LogFormat;
+use crate::worker_logs;
+
+use tokio::time::Duration;
+
+const ENV_PREFIX: &str = "MB_";
+
+// Settings whose flag may be given without a value, for true
+const SWITCHES: [&str; 1] = ["open_registration"];
+
+const KEYS: [&str; 23] = ["http_addr", "registration_addr", "discovery_addr", "worker_port", "sweep_interval_ms", "connect_timeout_ms", "open_registration", "tls_cert", "tls_key", "max_message_len", "rate_limit_per_minute", "rate_limit_burst", "worker_cooldown_ms", "degraded_after_misses", "offline_after_misses", "worker_retention_ms", "event_log", "log_filter", "log_format", "worker_log_lines", "firmware_dir", "firmware_url", "firmware_public_key"];
+
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Address the portal and API listen on.
+    pub http_addr: SocketAddr,
+    /// Address workers send `REGISTER` requests to.
+    pub registration_addr: SocketAddr,
+    /// UDP address answering workers looking for the server.
+    pub discovery_addr: SocketAddr,
+    /// Port directives are sent to when a worker doesn't name one on registration.
+    pub worker_port: u16,
+    /// Pause between two broadcasts to all workers.
+    pub sweep_interval: Duration,
+    /// How long to wait for a worker to accept a directive before considering it gone.
+    pub connect_timeout: Duration,
+    /// Whether workers without a registration key are accepted, off unless the operator opts in.
+    pub open_registration: bool,
+    /// PEM certificate chain for serving the portal over HTTPS.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key matching `tls_cert`.
+    pub tls_key: Option<PathBuf>,
+    /// Pre-shared registration keys by MAC address.
+    pub worker_keys: Vec<(String, Vec<u8>)>,
+    /// Users and tokens allowed on the portal and API.
+    pub access: AccessControl,
+    /// Checks on the text sent to workers.
+    pub content: ContentPolicy,
+    /// How fast clients may send commands, see [`crate::limits`].
+    pub rate_limits: RateLimits,
+    /// When unreachable workers count as degraded, offline and gone, see [`crate::liveness`].
+    pub liveness: LivenessPolicy,
+    /// File the event log is appended to, see [`crate::events`].
+    pub event_log: Option<PathBuf>,
+    /// Which log lines are kept, see [`crate::logging`].
+    pub log_filter: String,
+    pub log_format: LogFormat,
+    /// Lines forwarded by each worker kept for the portal, see [`crate::worker_logs`].
+    pub worker_log_lines: usize,
+    /// Directory uploaded firmware images are kept in, see [`crate::firmware`].
+    pub firmware_dir: Option<PathBuf>,
+    /// Where workers download images from, instead of the address they were reached on.
+    pub firmware_url: Option<String>,
+    /// Only images signed by the matching release key are taken if given.
+    pub firmware_public_key: Option<[u8; ota::KEY_LEN]>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            http_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 8091)),
+            registration_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, config::BROADCAST_PORT)),
+            discovery_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, discovery::DISCOVERY_PORT)),
+            worker_port: config::BROADCAST_PORT,
+            sweep_interval: Duration::from_millis(1000),
+            connect_timeout: Duration::from_millis(5000),
+            open_registration: false,
+            tls_cert: None,
+            tls_key: None,
+            worker_keys: Vec::new(),
+            access: AccessControl::default(),
+            content: ContentPolicy::default(),
+            rate_limits: RateLimits::default(),
+            liveness: LivenessPolicy::default(),
+            event_log: Some(PathBuf::from("events.jsonl")),
+            log_filter: "info".to_string(),
+            log_format: LogFormat::Text,
+            worker_log_lines: worker_logs::DEFAULT_LINES,
+            firmware_dir: Some(PathBuf::from("firmware")),
+            firmware_url: None,
+            firmware_public_key: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    http_addr: Option<SocketAddr>,
+    registration_addr: Option<SocketAddr>,
+    discovery_addr: Option<SocketAddr>,
+    worker_port: Option<u16>,
+    sweep_interval_ms: Option<u64>,
+    connect_timeout_ms: Option<u64>,
+    open_registration: Option<bool>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    max_message_len: Option<usize>,
+    blocklist: Option<Vec<String>>,
+    rate_limit_per_minute: Option<u32>,
+    rate_limit_burst: Option<u32>,
+    worker_cooldown_ms: Option<u64>,
+    degraded_after_misses: Option<u32>,
+    offline_after_misses: Option<u32>,
+    worker_retention_ms: Option<u64>,
+    event_log: Option<PathBuf>,
+    log_filter: Option<String>,
+    log_format: Option<LogFormat>,
+    worker_log_lines: Option<usize>,
+    firmware_dir: Option<PathBuf>,
+    firmware_url: Option<String>,
+    firmware_public_key: Option<String>,
+    #[serde(default)]
+    worker_keys: BTreeMap<String, String>,
+    #[serde(default)]
+    users: BTreeMap<String, UserEntry>,
+    #[serde(default)]
+    tokens: BTreeMap<String, Role>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserEntry {
+    password: String,
+    role: Role,
+}
+
+impl ServerConfig {
+
+    /// Load the configuration from the process environment and command line.
+    pub fn load() -> Result<Self, String> {
+        Self::from_sources(std::env::vars(), std::env::args().skip(1))
+    }
+
+    pub fn from_sources(env: impl IntoIterator<Item = (String, String)>, args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let env: Vec<(String, String)> = env.into_iter().filter(|(k, _)| k.starts_with(ENV_PREFIX)).collect();
+
+        // Flags are gathered first since one of them may name the config file
+        let mut flags: Vec<(String, String)> = Vec::new();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            let key = arg.strip_prefix("--").ok_or_else(|| format!("Unexpected argument {}", arg))?.replace('-', "_");
+            let value = match args.next_if(|next| !next.starts_with("--")) {
+                Some(value) => value,
+                None if SWITCHES.contains(&key.as_str()) => "true".to_string(),
+                None => return Err(format!("Missing value for {}", arg)),
+            };
+            flags.push((key, value));
+        }
+
+        let config_path = flags.iter().rev().find(|(k, _)| k == "config").map(|(_, v)| v.clone())
+            .or_else(|| env.iter().find(|(k, _)| k == "MB_CONFIG").map(|(_, v)| v.clone()));
+
+        let mut server_config = ServerConfig::default();
+
+        if let Some(path) = config_path {
+            let contents = std::fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+            server_config.apply_file(&contents).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        }
+
+        // Other MB_ variables are none of our business
+        for (name, value) in &env {
+            let key = name[ENV_PREFIX.len()..].to_lowercase();
+            if KEYS.contains(&key.as_str()) {
+                server_config.set(&key, value).map_err(|e| format!("{}: {}", name, e))?;
+            }
+        }
+
+        for (key, value) in &flags {
+            if key != "config" {
+                server_config.set(key, value).map_err(|e| format!("--{}: {}", key.replace('_', "-"), e))?;
+            }
+        }
+
+        if server_config.tls_cert.is_some() != server_config.tls_key.is_some() {
+            return Err("tls_cert and tls_key have to be given together".to_string());
+        }
+
+        Ok(server_config)
+    }
+
+    fn apply_file(&mut self, contents: &str) -> Result<(), String> {
+        let file: ConfigFile = toml::from_str(contents).map_err(|e| e.to_string())?;
+
+        if let Some(v) = file.http_addr { self.http_addr = v; }
+        if let Some(v) = file.registration_addr { self.registration_addr = v; }
+        if let Some(v) = file.discovery_addr { self.discovery_addr = v; }
+        if let Some(v) = file.worker_port { self.worker_port = v; }
+        if let Some(v) = file.sweep_interval_ms { self.sweep_interval = Duration::from_millis(v); }
+        if let Some(v) = file.connect_timeout_ms { self.connect_timeout = Duration::from_millis(v); }
+        if let Some(v) = file.open_registration { self.open_registration = v; }
+        if let Some(v) = file.tls_cert { self.tls_cert = Some(v); }
+        if let Some(v) = file.tls_key { self.tls_key = Some(v); }
+        if let Some(v) = file.max_message_len { self.content.max_len = v; }
+        if let Some(v) = file.blocklist { self.content.blocklist = v; }
+        if let Some(v) = file.rate_limit_per_minute { self.rate_limits.per_minute = v; }
+        if let Some(v) = file.rate_limit_burst { self.rate_limits.burst = v; }
+        if let Some(v) = file.worker_cooldown_ms { self.rate_limits.cooldown = Duration::from_millis(v); }
+        if let Some(v) = file.degraded_after_misses { self.liveness.degraded_after = v; }
+        if let Some(v) = file.offline_after_misses { self.liveness.offline_after = v; }
+        if let Some(v) = file.worker_retention_ms { self.liveness.retention = Duration::from_millis(v); }
+        if let Some(v) = file.event_log { self.event_log = Some(v).filter(|p| !p.as_os_str().is_empty()); }
+        if let Some(v) = file.log_filter { self.log_filter = v; }
+        if let Some(v) = file.log_format { self.log_format = v; }
+        if let Some(v) = file.worker_log_lines { self.worker_log_lines = v; }
+        if let Some(v) = file.firmware_dir { self.firmware_dir = Some(v).filter(|p| !p.as_os_str().is_empty()); }
+        if let Some(v) = file.firmware_url { self.firmware_url = Some(v).filter(|u| !u.is_empty()); }
+        if let Some(v) = file.firmware_public_key { self.firmware_public_key = parse_public_key(&v)?; }
+
+        for (mac_address, key) in file.worker_keys {
+            let key = registration::parse_key(&key).ok_or_else(|| format!("invalid key for {}, expected at least 16 bytes of hex", mac_address))?;
+            self.worker_keys.push((mac_address, key));
+        }
+
+        for (name, user) in file.users {
+            self.access.add_user(&name, &user.password, user.role);
+        }
+        for (token, role) in file.tokens {
+            self.access.add_token(&token, role);
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "http_addr"             => self.http_addr = parse(value)?,
+            "registration_addr"     => self.registration_addr = parse(value)?,
+            "discovery_addr"        => self.discovery_addr = parse(value)?,
+            "worker_port"           => self.worker_port = parse(value)?,
+            "sweep_interval_ms"     => self.sweep_interval = Duration::from_millis(parse(value)?),
+            "connect_timeout_ms"    => self.connect_timeout = Duration::from_millis(parse(value)?),
+            "open_registration"     => self.open_registration = parse(value)?,
+            "tls_cert"              => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key"               => self.tls_key = Some(PathBuf::from(value)),
+            "max_message_len"       => self.content.max_len = parse(value)?,
+            "rate_limit_per_minute" => self.rate_limits.per_minute = parse(value)?,
+            "rate_limit_burst"      => self.rate_limits.burst = parse(value)?,
+            "worker_cooldown_ms"    => self.rate_limits.cooldown = Duration::from_millis(parse(value)?),
+            "degraded_after_misses" => self.liveness.degraded_after = parse(value)?,
+            "offline_after_misses"  => self.liveness.offline_after = parse(value)?,
+            "worker_retention_ms"   => self.liveness.retention = Duration::from_millis(parse(value)?),
+            "event_log"             => self.event_log = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "log_filter"            => self.log_filter = value.to_string(),
+            "log_format"            => self.log_format = parse(value)?,
+            "worker_log_lines"      => self.worker_log_lines = parse(value)?,
+            "firmware_dir"          => self.firmware_dir = Some(PathBuf::from(value)).filter(|p| !p.as_os_str().is_empty()),
+            "firmware_url"          => self.firmware_url = Some(value.to_string()).filter(|u| !u.is_empty()),
+            "firmware_public_key"   => self.firmware_public_key = parse_public_key(value)?,
+            _ => return Err(format!("unknown setting {}", key)),
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    T::from_str(value).map_err(|e| format!("invalid value '{}': {}", value, e))
+}
+
+// Empty for none
+fn parse_public_key(value: &str) -> Result<Option<[u8; ota::KEY_LEN]>, String> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    ota::parse_hex(value).map(Some).ok_or_else(|| format!("invalid public key, expected {} bytes of hex", ota::KEY_LEN))
+}
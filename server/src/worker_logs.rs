@@ -0,0 +1,67 @@
+//! What workers logged, as they forwarded it.
+//!
+//! Workers keep the lines they log in a ring buffer and send them along with their answer to the
+//! next directive (see [`telemetry::LogLine`]). The server keeps the latest lines of each worker
+//! in memory, for `/api/workers/{mac}/logs` and the portal, so a misbehaving board can be looked
+//! at without a USB cable. Lines are timestamped when they arrive, workers don't know the time.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use telemetry::LogLine;
+
+/// Lines kept per worker unless configured otherwise.
+pub const DEFAULT_LINES: usize = 200;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct WorkerLogEntry {
+    /// Seconds since the Unix epoch the server got the line.
+    pub time: u64,
+    pub level: String,
+    pub message: String,
+}
+
+pub struct WorkerLogs {
+    lines: Mutex<HashMap<String, VecDeque<WorkerLogEntry>>>,
+    capacity: usize,
+}
+
+impl WorkerLogs {
+
+    /// Keep the last `capacity` lines of each worker.
+    pub fn new(capacity: usize) -> Self {
+        WorkerLogs { lines: Mutex::new(HashMap::new()), capacity }
+    }
+
+    pub fn record(&self, mac_address: &str, line: LogLine) {
+        if self.capacity == 0 {
+            return;
+        }
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+        let mut lines = self.lines.lock().unwrap();
+        let worker = lines.entry(mac_address.to_string()).or_default();
+        if worker.len() == self.capacity {
+            worker.pop_front();
+        }
+        worker.push_back(WorkerLogEntry { time, level: line.level, message: line.message });
+    }
+
+    /// The lines kept for the worker at `mac_address`, oldest first.
+    pub fn lines(&self, mac_address: &str) -> Vec<WorkerLogEntry> {
+        self.lines.lock().unwrap().get(mac_address).map(|l| l.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    pub fn forget(&self, mac_address: &str) {
+        self.lines.lock().unwrap().remove(mac_address);
+    }
+}
+
+impl Default for WorkerLogs {
+    fn default() -> Self {
+        Self::new(DEFAULT_LINES)
+    }
+}
@@ -0,0 +1,119 @@
+// Every role against every kind of route, over real HTTP requests to the portal.
+
+use base64::Engine;
+
+use server::auth::{AccessControl, Role};
+use server::settings::ServerConfig;
+
+mod common;
+use common::{Setup, TestServer};
+
+const KEY: &str = "00112233445566778899aabbccddeeff";
+
+async fn start(access: AccessControl) -> TestServer {
+    TestServer::with(Setup { config: ServerConfig { access, ..common::config() }, ..Setup::default() }).await
+}
+
+async fn with_roles() -> TestServer {
+    let mut access = AccessControl::default();
+    access.add_user("viewer", "look", Role::Viewer);
+    access.add_user("operator", "send", Role::Operator);
+    access.add_user("admin", "manage", Role::Admin);
+    access.add_token("scoreboard-token", Role::Operator);
+    start(access).await
+}
+
+impl TestServer {
+
+    async fn portal(&self, authorization: Option<String>) -> u16 {
+        self.request("GET", "/", authorization.as_deref(), "").await.status
+    }
+
+    async fn send_message(&self, authorization: Option<String>) -> u16 {
+        self.request("POST", "/messaging", authorization.as_deref(), r#"{"id":"Broadcast","message":"Hi"}"#).await.status
+    }
+
+    async fn set_key(&self, authorization: Option<String>) -> u16 {
+        let body = format!(r#"{{"id":"02:00:00:00:00:01","key":"{}"}}"#, KEY);
+        self.request("POST", "/registry/key", authorization.as_deref(), &body).await.status
+    }
+}
+
+fn basic(name: &str, password: &str) -> Option<String> {
+    Some(format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", name, password))))
+}
+
+#[tokio::test]
+async fn anonymous_requests_are_asked_to_log_in() {
+    let server = with_roles().await;
+
+    let response = server.request("GET", "/", None, "").await;
+    assert_eq!(response.status, 401);
+    assert!(response.head.contains("www-authenticate: Basic realm=\"MicroBroadcast\""), "unexpected response: {}", response.head);
+
+    assert_eq!(server.send_message(None).await, 401);
+    assert_eq!(server.set_key(None).await, 401);
+}
+
+#[tokio::test]
+async fn wrong_password_is_refused() {
+    let server = with_roles().await;
+
+    assert_eq!(server.portal(basic("admin", "guess")).await, 401);
+    assert_eq!(server.portal(basic("nobody", "manage")).await, 401);
+    assert_eq!(server.portal(Some("Bearer guess".to_string())).await, 401);
+}
+
+#[tokio::test]
+async fn viewer_is_read_only() {
+    let server = with_roles().await;
+
+    let portal = server.request("GET", "/", basic("viewer", "look").as_deref(), "").await.ok();
+    assert!(portal.contains("if (true)"), "viewer portal should disable its buttons");
+
+    assert_eq!(server.send_message(basic("viewer", "look")).await, 403);
+    assert_eq!(server.set_key(basic("viewer", "look")).await, 403);
+}
+
+#[tokio::test]
+async fn operator_sends_commands_but_not_registry_changes() {
+    let server = with_roles().await;
+
+    let portal = server.request("GET", "/", basic("operator", "send").as_deref(), "").await.ok();
+    assert!(portal.contains("if (false)"), "operator portal should keep its buttons");
+
+    assert_eq!(server.send_message(basic("operator", "send")).await, 200);
+    assert_eq!(server.set_key(basic("operator", "send")).await, 403);
+    assert!(server.registry.read().get_key("02:00:00:00:00:01").is_none());
+}
+
+#[tokio::test]
+async fn admin_manages_the_registry() {
+    let server = with_roles().await;
+
+    assert_eq!(server.portal(basic("admin", "manage")).await, 200);
+    assert_eq!(server.send_message(basic("admin", "manage")).await, 200);
+    assert_eq!(server.set_key(basic("admin", "manage")).await, 200);
+    assert!(server.registry.read().get_key("02:00:00:00:00:01").is_some());
+
+    server.request("POST", "/registry/key", basic("admin", "manage").as_deref(), r#"{"id":"02:00:00:00:00:01","key":""}"#).await.ok();
+    assert!(server.registry.read().get_key("02:00:00:00:00:01").is_none());
+}
+
+#[tokio::test]
+async fn token_carries_its_role() {
+    let server = with_roles().await;
+    let token = Some("Bearer scoreboard-token".to_string());
+
+    assert_eq!(server.send_message(token.clone()).await, 200);
+    assert_eq!(server.set_key(token).await, 403);
+}
+
+#[tokio::test]
+async fn without_accounts_everything_is_open() {
+    let server = start(AccessControl::default()).await;
+
+    assert_eq!(server.portal(None).await, 200);
+    assert_eq!(server.send_message(None).await, 200);
+    assert_eq!(server.set_key(None).await, 200);
+}
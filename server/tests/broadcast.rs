@@ -0,0 +1,184 @@
+// End to end tests: the portal API, the registration listener and the broadcasting loop run on
+// ephemeral ports and talk to scripted fake workers over real sockets.
+
+use tokio::net::TcpListener;
+use tokio::time::Duration;
+
+use server::liveness::LivenessPolicy;
+
+mod common;
+use common::{next_directive, wait_until, Setup, TestServer, GEORGIA};
+
+async fn start() -> TestServer {
+    // Forget unreachable workers quickly, the grace period has its own tests
+    let liveness = LivenessPolicy { retention: Duration::from_millis(100), ..LivenessPolicy::default() };
+    TestServer::with(Setup { liveness, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    fn worker_state(&self, mac_address: &str) -> Option<bool> {
+        self.registry.read().get_worker(mac_address).map(|w| w.active)
+    }
+
+    async fn wait_for_state(&self, mac_address: &str, state: Option<bool>) {
+        let waiting_for = format!("{} to reach state {:?}", mac_address, state);
+        wait_until(&waiting_for, async || (self.worker_state(mac_address) == state).then_some(())).await
+    }
+}
+
+struct FakeWorker {
+    mac_address: String,
+    listener: TcpListener,
+}
+
+impl FakeWorker {
+
+    async fn register(server: &TestServer, mac_address: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        server.register(mac_address, listener.local_addr().unwrap().port()).await;
+        server.wait_for_state(mac_address, Some(true)).await;
+
+        FakeWorker { mac_address: mac_address.to_string(), listener }
+    }
+
+    async fn next_directive(&self) -> String {
+        next_directive(&self.listener).await
+    }
+
+    /// Skip directives until one satisfies `predicate`.
+    async fn wait_for(&self, predicate: impl Fn(&str) -> bool) -> String {
+        let waiting_for = format!("the expected directive to {}", self.mac_address);
+        wait_until(&waiting_for, async || Some(self.next_directive().await).filter(|d| predicate(d))).await
+    }
+}
+
+#[tokio::test]
+async fn registered_worker_is_pinged() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    assert_eq!(worker.next_directive().await, "PING");
+}
+
+#[tokio::test]
+async fn broadcast_message_reaches_every_worker() {
+    let server = start().await;
+    let a = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+    let b = FakeWorker::register(&server, "02:00:00:00:00:02").await;
+
+    let receipt = server.post("/messaging", r#"{"id":"Broadcast","message":"Hello all"}"#).await;
+    assert_eq!(receipt, r#"{"status":"Complete"}"#);
+
+    a.wait_for(|d| d == "MESSAGE Hello all").await;
+    b.wait_for(|d| d == "MESSAGE Hello all").await;
+}
+
+#[tokio::test]
+async fn targeted_message_only_reaches_its_worker() {
+    let server = start().await;
+    let a = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+    let b = FakeWorker::register(&server, "02:00:00:00:00:02").await;
+
+    server.post("/messaging", r#"{"id":"02:00:00:00:00:01","message":"Just you"}"#).await;
+
+    a.wait_for(|d| d == "MESSAGE Just you").await;
+
+    // b keeps getting pinged through a few sweeps
+    for _ in 0..3 {
+        assert_eq!(b.next_directive().await, "PING");
+    }
+}
+
+#[tokio::test]
+async fn unknown_worker_is_unavailable() {
+    let server = start().await;
+
+    let receipt = server.post("/messaging", r#"{"id":"02:00:00:00:00:99","message":"Anyone?"}"#).await;
+    assert_eq!(receipt, r#"{"status":"Unavailable"}"#);
+}
+
+#[tokio::test]
+async fn timer_start_and_add() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    server.post("/timerStart", r#"{"id":"02:00:00:00:00:01","duration":"5"}"#).await;
+    let directive = worker.wait_for(|d| d.starts_with("TIMER ")).await;
+    assert!(directive.ends_with("/300"), "unexpected timer {}", directive);
+
+    server.post("/timerAdd", r#"{"id":"02:00:00:00:00:01","duration":"5"}"#).await;
+    worker.wait_for(|d| d.starts_with("TIMER ") && d.ends_with("/600")).await;
+}
+
+#[tokio::test]
+async fn timer_add_without_a_timer_starts_one() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    server.post("/timerAdd", r#"{"id":"Broadcast","duration":"5"}"#).await;
+    worker.wait_for(|d| d.starts_with("TIMER ") && d.ends_with("/300")).await;
+}
+
+#[tokio::test]
+async fn animation_is_forwarded() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    server.post("/animation", r#"{"id":"Broadcast","animation":"Heart"}"#).await;
+    worker.wait_for(|d| d == "ANIMATE Heart").await;
+}
+
+#[tokio::test]
+async fn scene_is_forwarded() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    server.post("/scene", r#"{"id":"Broadcast","animation":"CartoonEyes","duration":"","message":"Hi"}"#).await;
+    worker.wait_for(|d| d == "SCENE CartoonEyes||Hi").await;
+}
+
+#[tokio::test]
+async fn invalid_commands_are_refused() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    for (path, body) in [
+        ("/timerStart", r#"{"id":"Broadcast","duration":"five"}"#),
+        ("/timerAdd", r#"{"id":"Broadcast","duration":"307445734561825861"}"#),
+        ("/animation", r#"{"id":"Broadcast","animation":"Dragon"}"#),
+        ("/scene", r#"{"id":"Broadcast","animation":"Heart|1/2","duration":"","message":"Hi"}"#),
+        ("/scene", r#"{"id":"Broadcast","animation":"Heart","duration":"-1","message":"Hi"}"#),
+    ] {
+        assert_eq!(server.post(path, body).await, r#"{"status":"Invalid"}"#, "{} {}", path, body);
+    }
+
+    // A scene without an animation is fine
+    server.post("/scene", r#"{"id":"Broadcast","animation":"","duration":"","message":"Hi"}"#).await;
+    assert_eq!(worker.wait_for(|d| d != "PING").await, "SCENE ||Hi");
+}
+
+#[tokio::test]
+async fn unreachable_worker_is_removed() {
+    let server = start().await;
+    let worker = FakeWorker::register(&server, "02:00:00:00:00:01").await;
+
+    drop(worker);
+
+    server.wait_for_state("02:00:00:00:00:01", None).await;
+}
+
+#[tokio::test]
+async fn persistent_worker_goes_inactive_and_returns() {
+    let server = start().await;
+    assert_eq!(server.worker_state(GEORGIA), Some(false));
+
+    let worker = FakeWorker::register(&server, GEORGIA).await;
+    drop(worker);
+
+    // Persistent workers are kept, just marked inactive
+    server.wait_for_state(GEORGIA, Some(false)).await;
+
+    let worker = FakeWorker::register(&server, GEORGIA).await;
+    assert_eq!(worker.next_directive().await, "PING");
+}
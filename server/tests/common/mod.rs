@@ -0,0 +1,203 @@
+// The server the end to end tests run against: the portal, the registration listener and the
+// broadcasting loop on ephemeral ports, talked to over real sockets.
+
+// Each test file uses its own share of this
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::time::{sleep, timeout, Duration, Instant};
+
+use server::events::Event;
+use server::limits::RateLimits;
+use server::liveness::LivenessPolicy;
+use server::registry::Registry;
+use server::settings::ServerConfig;
+use telemetry::Telemetry;
+
+pub const WAIT: Duration = Duration::from_secs(5);
+
+// Workers of the server's persistent table, known whether registered or not
+pub const GEORGIA: &str = "EC:DA:3B:BF:46:9C";
+pub const ASHER: &str = "EC:DA:3B:BF:49:2C";
+
+/// The server's defaults, without a cooldown: tests send commands back to back, the cooldown has
+/// tests of its own.
+pub fn config() -> ServerConfig {
+    let rate_limits = RateLimits { cooldown: Duration::ZERO, ..RateLimits::default() };
+    ServerConfig { rate_limits, ..ServerConfig::default() }
+}
+
+/// What a [`TestServer`] is started with, quick sweeps unless said otherwise.
+pub struct Setup {
+    pub registry: Registry,
+    /// `http_addr` is replaced by the address the portal ends up on.
+    pub config: ServerConfig,
+    pub sweep_interval: Duration,
+    pub connect_timeout: Duration,
+    pub liveness: LivenessPolicy,
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Setup {
+            registry: Registry::default(),
+            config: config(),
+            sweep_interval: Duration::from_millis(50),
+            connect_timeout: Duration::from_millis(500),
+            liveness: LivenessPolicy::default(),
+        }
+    }
+}
+
+pub struct TestServer {
+    pub http: SocketAddr,
+    pub registration: SocketAddr,
+    pub registry: Registry,
+}
+
+pub struct Response {
+    pub status: u16,
+    /// Status line and headers.
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// The body of a response that has to be a 200.
+    pub fn ok(self) -> String {
+        assert_eq!(self.status, 200, "unexpected response: {}{}", self.head, self.text());
+        self.text()
+    }
+}
+
+impl TestServer {
+
+    pub async fn start() -> Self {
+        Self::with(Setup::default()).await
+    }
+
+    pub async fn with(setup: Setup) -> Self {
+        let Setup { registry, config: setup_config, sweep_interval, connect_timeout, liveness } = setup;
+
+        let http_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let registration_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+        let http = http_listener.local_addr().unwrap();
+        let registration = registration_listener.local_addr().unwrap();
+
+        // Workers are pointed at the portal's port
+        let server_config = ServerConfig { http_addr: http, ..setup_config };
+        let app = server::app(registry.clone(), &server_config);
+        tokio::spawn(async move { axum::serve(http_listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap() });
+        tokio::spawn(server::registration_loop(registry.clone(), registration_listener, config::BROADCAST_PORT, true));
+        tokio::spawn(server::broadcast_loop(registry.clone(), sweep_interval, connect_timeout, liveness));
+
+        TestServer { http, registration, registry }
+    }
+
+    // Minimal HTTP/1.1 client, `headers` go in as they are
+    pub async fn send(&self, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        self.send_from(Ipv4Addr::LOCALHOST, method, path, headers, body).await
+    }
+
+    /// [`TestServer::send`] from the loopback address `from`, a client of its own to the server.
+    pub async fn send_from(&self, from: Ipv4Addr, method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Response {
+        let socket = TcpSocket::new_v4().unwrap();
+        socket.bind(SocketAddr::from((from, 0))).unwrap();
+        let mut stream = socket.connect(self.http).await.unwrap();
+
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, path);
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()));
+        stream.write_all(head.as_bytes()).await.unwrap();
+        stream.write_all(body).await.unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await.unwrap();
+
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..split]).to_string();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        Response { status, head, body: response[split + 4..].to_vec() }
+    }
+
+    /// A JSON request, with an `Authorization` header if given.
+    pub async fn request(&self, method: &str, path: &str, authorization: Option<&str>, body: &str) -> Response {
+        let mut headers = vec![("Content-Type", "application/json")];
+        if let Some(authorization) = authorization {
+            headers.push(("Authorization", authorization));
+        }
+        self.send(method, path, &headers, body.as_bytes()).await
+    }
+
+    pub async fn get(&self, path: &str) -> String {
+        self.request("GET", path, None, "").await.ok()
+    }
+
+    pub async fn post(&self, path: &str, body: &str) -> String {
+        self.request("POST", path, None, body).await.ok()
+    }
+
+    /// Register the way workers without a key do, answering directives on `port`.
+    pub async fn register(&self, mac_address: &str, port: u16) {
+        self.register_line(&format!("REGISTER {} {}", mac_address, port)).await;
+    }
+
+    /// Send `line` to the registration listener and hang up.
+    pub async fn register_line(&self, line: &str) {
+        let mut stream = TcpStream::connect(self.registration).await.unwrap();
+        stream.write_all(line.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+    }
+
+    /// Go through the exchange, following up with `telemetry` if given.
+    pub async fn register_with(&self, mac_address: &str, port: u16, telemetry: Option<&Telemetry>) {
+        let stream = TcpStream::connect(self.registration).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        writer.write_all(registration::request(mac_address, Some(port)).as_bytes()).await.unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line.trim(), registration::ACCEPTED);
+
+        if let Some(telemetry) = telemetry {
+            writer.write_all(telemetry.line().as_bytes()).await.unwrap();
+        }
+    }
+
+    pub async fn wait_for_event(&self, predicate: impl Fn(&Event) -> bool) -> Event {
+        wait_until("the expected event", async || self.registry.events().query(None, None).into_iter().find(&predicate)).await
+    }
+}
+
+/// Poll `check` until it comes back with something, the test fails if that takes longer than
+/// [`WAIT`].
+pub async fn wait_until<T>(waiting_for: &str, mut check: impl AsyncFnMut() -> Option<T>) -> T {
+    let deadline = Instant::now() + WAIT;
+    loop {
+        if let Some(found) = check().await {
+            return found;
+        }
+        assert!(Instant::now() < deadline, "gave up waiting for {}", waiting_for);
+        sleep(Duration::from_millis(20)).await;
+    }
+}
+
+/// The next directive a worker listening on `listener` is sent.
+pub async fn next_directive(listener: &TcpListener) -> String {
+    let (mut socket, _) = timeout(WAIT, listener.accept()).await.expect("no directive received").unwrap();
+    let mut directive = String::new();
+    socket.read_to_string(&mut directive).await.unwrap();
+    directive
+}
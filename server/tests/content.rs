@@ -0,0 +1,74 @@
+// Text from the portal as it ends up on a worker's screen.
+
+use server::content::{transliterate, ContentPolicy, Rejection};
+
+fn policy(blocklist: &[&str]) -> ContentPolicy {
+    ContentPolicy { blocklist: blocklist.iter().map(|w| w.to_string()).collect(), ..ContentPolicy::default() }
+}
+
+#[test]
+fn letters_workers_can_draw_are_kept() {
+    assert_eq!(transliterate("Crème brûlée à São Paulo"), "Crème brûlée à São Paulo");
+    assert_eq!(transliterate("Łódź, Dvořák, Œuvre"), "Łódź, Dvořák, Œuvre");
+}
+
+#[test]
+fn other_letters_lose_their_accents() {
+    assert_eq!(transliterate("Ēriks, İzmir"), "Eriks, Izmir");
+}
+
+#[test]
+fn typographic_punctuation_becomes_ascii() {
+    assert_eq!(transliterate("“Quiet” – it’s 20° out…"), "\"Quiet\" - it's 20° out...");
+}
+
+#[test]
+fn emoji_with_an_icon_are_kept() {
+    assert_eq!(transliterate("Well done ❤️ 🙂"), "Well done ❤ 🙂");
+    assert_eq!(transliterate("👍🏽"), "👍");
+}
+
+#[test]
+fn other_emoji_become_smileys() {
+    assert_eq!(transliterate("😎 🎉"), "B) \\o/");
+    assert_eq!(transliterate("🦀"), "?");
+}
+
+#[test]
+fn line_endings_are_normalized() {
+    assert_eq!(transliterate("one\r\ntwo\rthree\tfour"), "one\ntwo\nthree four");
+}
+
+#[test]
+fn empty_text_is_rejected() {
+    let policy = ContentPolicy::default();
+
+    assert_eq!(policy.sanitize(""), Err(Rejection::Empty));
+    assert_eq!(policy.sanitize(" \r\n\u{200D}"), Err(Rejection::Empty));
+}
+
+#[test]
+fn length_is_counted_after_transliteration() {
+    let policy = ContentPolicy { max_len: 5, ..ContentPolicy::default() };
+
+    assert_eq!(policy.sanitize("Hello").as_deref(), Ok("Hello"));
+    assert_eq!(policy.sanitize("Hello  \n"), Ok("Hello".to_string()));
+    // An ellipsis takes three characters on the screen
+    assert_eq!(policy.sanitize("Hey…"), Err(Rejection::TooLong { len: 6, max: 5 }));
+}
+
+#[test]
+fn blocked_words_are_masked() {
+    let policy = policy(&["darn"]);
+
+    assert_eq!(policy.sanitize("Darn, the DARN bus").as_deref(), Ok("****, the **** bus"));
+    // Only whole words
+    assert_eq!(policy.sanitize("darned darnation").as_deref(), Ok("darned darnation"));
+}
+
+#[test]
+fn blocked_words_cannot_hide_behind_accents() {
+    let policy = policy(&["heck"]);
+
+    assert_eq!(policy.sanitize("what the hëck").as_deref(), Ok("what the ****"));
+}
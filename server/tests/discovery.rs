@@ -0,0 +1,31 @@
+// Discovery against the server's responder on a local socket, as a board would do it on boot.
+
+use std::net::{Ipv4Addr, SocketAddr};
+
+use tokio::net::UdpSocket;
+use tokio::time::Duration;
+
+#[tokio::test]
+async fn worker_finds_local_responder() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let responder = socket.local_addr().unwrap();
+
+    tokio::spawn(server::discovery_loop(socket, 4242));
+
+    let found = tokio::task::spawn_blocking(move || discovery::discover(responder, Duration::from_millis(200), 3))
+        .await.unwrap().unwrap();
+
+    assert_eq!(found, Some(SocketAddr::from((Ipv4Addr::LOCALHOST, 4242))));
+}
+
+#[tokio::test]
+async fn discovery_gives_up_without_a_server() {
+    // Bound but never answering
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = silent.local_addr().unwrap();
+
+    let found = tokio::task::spawn_blocking(move || discovery::discover(target, Duration::from_millis(50), 2))
+        .await.unwrap().unwrap();
+
+    assert_eq!(found, None);
+}
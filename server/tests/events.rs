@@ -0,0 +1,152 @@
+// The event log on its own, on disk, and as recorded by the server at work.
+
+use std::path::PathBuf;
+
+use base64::Engine;
+
+use tokio::net::TcpListener;
+use tokio::time::{sleep, Duration};
+
+use server::auth::{AccessControl, Role};
+use server::events::{EventKind, EventLog};
+use server::liveness::Liveness;
+use server::settings::ServerConfig;
+
+mod common;
+use common::{next_directive, wait_until, Setup, TestServer};
+
+fn temp_log(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mb-events-{}-{}.jsonl", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn registered(worker: &str) -> EventKind {
+    EventKind::Registered { worker: worker.to_string(), address: "127.0.0.1".to_string() }
+}
+
+#[test]
+fn events_are_numbered_and_filtered() {
+    let log = EventLog::in_memory();
+
+    log.record(registered("02:00:00:00:00:01"));
+    log.record(EventKind::Command { target: "Broadcast".to_string(), command: "animation Heart".to_string(), origin: "anyone".to_string() });
+    log.record(registered("02:00:00:00:00:02"));
+
+    let ids: Vec<u64> = log.query(None, None).iter().map(|e| e.id).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    assert_eq!(log.query(Some(2), None).len(), 1);
+
+    // Broadcasts concern every worker
+    let ids: Vec<u64> = log.query(None, Some("02:00:00:00:00:01")).iter().map(|e| e.id).collect();
+    assert_eq!(ids, [1, 2]);
+
+    assert_eq!(log.latest(2).iter().map(|e| e.id).collect::<Vec<_>>(), [3, 2]);
+}
+
+#[test]
+fn log_survives_a_restart() {
+    let path = temp_log("restart");
+
+    let log = EventLog::open(&path).unwrap();
+    log.record(registered("02:00:00:00:00:01"));
+    log.record(EventKind::Liveness { worker: "02:00:00:00:00:01".to_string(), liveness: Liveness::Offline });
+    drop(log);
+
+    let log = EventLog::open(&path).unwrap();
+    let event = log.record(registered("02:00:00:00:00:01"));
+    assert_eq!(event.id, 3);
+
+    let events = log.query(None, None);
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[1].kind, EventKind::Liveness { worker: "02:00:00:00:00:01".to_string(), liveness: Liveness::Offline });
+
+    // Appended, one JSON object per line
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents.lines().count(), 3);
+    assert!(contents.lines().next().unwrap().contains(r#""type":"registered""#), "unexpected log: {}", contents);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn truncated_lines_are_skipped() {
+    let path = temp_log("truncated");
+    std::fs::write(&path, "{\"id\":1,\"time\":0,\"type\":\"removed\",\"worker\":\"A\",\"reason\":\"x\"}\n{\"id\":2,\"ti").unwrap();
+
+    let log = EventLog::open(&path).unwrap();
+    assert_eq!(log.query(None, None).len(), 1);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+async fn start(access: AccessControl) -> TestServer {
+    TestServer::with(Setup { config: ServerConfig { access, ..common::config() }, ..Setup::default() }).await
+}
+
+fn basic(name: &str, password: &str) -> String {
+    format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", name, password)))
+}
+
+#[tokio::test]
+async fn commands_are_logged_with_who_sent_them_and_their_delivery() {
+    let mut access = AccessControl::default();
+    access.add_user("teacher", "chalk", Role::Admin);
+    let server = start(access).await;
+    let auth = basic("teacher", "chalk");
+
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register("02:00:00:00:00:01", worker.local_addr().unwrap().port()).await;
+    server.wait_for_event(|e| e.kind == registered("02:00:00:00:00:01")).await;
+
+    server.request("POST", "/messaging", Some(&auth), r#"{"id":"02:00:00:00:00:01","message":"Hello"}"#).await.ok();
+
+    let command = server.wait_for_event(|e| matches!(e.kind, EventKind::Command { .. })).await;
+    assert_eq!(command.kind, EventKind::Command {
+        target: "02:00:00:00:00:01".to_string(),
+        command: "message \"Hello\"".to_string(),
+        origin: "teacher@127.0.0.1".to_string(),
+    });
+
+    // The worker takes whatever comes until the message arrives
+    wait_until("the message", async || (next_directive(&worker).await == "MESSAGE Hello").then_some(())).await;
+
+    server.wait_for_event(|e| e.kind == EventKind::Delivered { worker: "02:00:00:00:00:01".to_string(), command: "message \"Hello\"".to_string() }).await;
+
+    // Logged once, not on every sweep
+    sleep(Duration::from_millis(200)).await;
+    let delivered = server.registry.events().query(None, None).iter().filter(|e| matches!(e.kind, EventKind::Delivered { .. })).count();
+    assert_eq!(delivered, 1);
+
+    let events = server.request("GET", &format!("/api/events?since={}", command.id), Some(&auth), "").await.ok();
+    assert!(events.starts_with(r#"[{"id":"#), "unexpected events: {}", events);
+    assert!(events.contains(r#""type":"delivered","worker":"02:00:00:00:00:01","command":"message \"Hello\"""#), "unexpected events: {}", events);
+    assert!(!events.contains(r#""type":"command""#), "unexpected events: {}", events);
+}
+
+#[tokio::test]
+async fn failed_deliveries_and_removals_are_logged() {
+    let server = start(AccessControl::default()).await;
+
+    // Registers, then goes away before anything reaches it
+    let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    server.register("02:00:00:00:00:02", port).await;
+    server.wait_for_event(|e| e.kind == registered("02:00:00:00:00:02")).await;
+
+    server.request("POST", "/animation", Some("Bearer none"), r#"{"id":"Broadcast","animation":"Heart"}"#).await.ok();
+    server.wait_for_event(|e| matches!(&e.kind, EventKind::DeliveryFailed { worker, .. } if worker == "02:00:00:00:00:02")).await;
+    server.wait_for_event(|e| e.kind == EventKind::Liveness { worker: "02:00:00:00:00:02".to_string(), liveness: Liveness::Offline }).await;
+
+    server.request("POST", "/registry/remove", Some("Bearer none"), r#"{"id":"02:00:00:00:00:02"}"#).await.ok();
+    let removed = server.wait_for_event(|e| matches!(e.kind, EventKind::Removed { .. })).await;
+    assert_eq!(removed.kind.details(), "removed by anyone@127.0.0.1");
+
+    // Only what concerns the worker, the broadcast included
+    let events = server.request("GET", "/api/events?worker=02:00:00:00:00:02", Some("Bearer none"), "").await.ok();
+    assert!(events.contains(r#""type":"command","target":"Broadcast""#), "unexpected events: {}", events);
+    assert!(events.contains(r#""type":"removed""#), "unexpected events: {}", events);
+
+    let events = server.request("GET", "/api/events?worker=02:00:00:00:00:09", Some("Bearer none"), "").await.ok();
+    assert!(events.contains(r#""type":"command""#), "unexpected events: {}", events);
+    assert!(!events.contains("02:00:00:00:00:02"), "unexpected events: {}", events);
+}
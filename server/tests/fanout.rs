@@ -0,0 +1,97 @@
+// Workers are swept concurrently: one that hangs or has gone away doesn't hold up the others.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio::time::{timeout, Duration, Instant};
+
+mod common;
+use common::{Setup, TestServer};
+
+// Long enough that waiting on a stuck worker would be obvious
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+async fn start() -> TestServer {
+    TestServer::with(Setup { connect_timeout: CONNECT_TIMEOUT, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    async fn send_message(&self, message: &str) {
+        self.post("/messaging", &format!(r#"{{"id":"Broadcast","message":"{}"}}"#, message)).await;
+    }
+}
+
+// Takes directives but never hangs up, so the server waits out its timeout on every one
+async fn stuck_worker() -> (u16, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((socket, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            held.push(socket);
+        }
+    });
+
+    (port, accepted)
+}
+
+// Nothing listens on the port it registers with
+async fn gone_worker() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+// Healthy worker, returns how long `message` took to arrive once sent
+async fn time_to_message(server: &TestServer, listener: &TcpListener, message: &str) -> Duration {
+    let expected = format!("MESSAGE {}", message);
+    let sent = Instant::now();
+    server.send_message(message).await;
+
+    loop {
+        let (mut socket, _) = timeout(CONNECT_TIMEOUT, listener.accept()).await.expect("healthy worker starved").unwrap();
+        let mut directive = String::new();
+        socket.read_to_string(&mut directive).await.unwrap();
+        if directive == expected {
+            return sent.elapsed();
+        }
+    }
+}
+
+#[tokio::test]
+async fn stuck_worker_does_not_delay_others() {
+    let server = start().await;
+
+    let (stuck_port, _) = stuck_worker().await;
+    server.register("02:00:00:00:00:01", stuck_port).await;
+    server.register("02:00:00:00:00:02", gone_worker().await).await;
+
+    let healthy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register("02:00:00:00:00:03", healthy.local_addr().unwrap().port()).await;
+
+    // Give the stuck worker time to hold a sweep open
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for message in ["One", "Two", "Three"] {
+        let elapsed = time_to_message(&server, &healthy, message).await;
+        assert!(elapsed < Duration::from_secs(1), "{} took {:?}", message, elapsed);
+    }
+}
+
+#[tokio::test]
+async fn stuck_worker_is_not_piled_on() {
+    let server = start().await;
+
+    let (stuck_port, accepted) = stuck_worker().await;
+    server.register("02:00:00:00:00:01", stuck_port).await;
+
+    // Many sweeps go by while the first directive is still waiting for an answer
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(accepted.load(Ordering::SeqCst), 1);
+}
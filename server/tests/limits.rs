@@ -0,0 +1,167 @@
+// Client rate limits and worker cooldowns, on their own and through the command API.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use tokio::time::{Duration, Instant};
+
+use server::limits::{RateLimiter, RateLimits};
+use server::settings::ServerConfig;
+
+mod common;
+use common::{Response, Setup, TestServer, ASHER, GEORGIA};
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+async fn start(rate_limits: RateLimits) -> TestServer {
+    TestServer::with(Setup { config: ServerConfig { rate_limits, ..ServerConfig::default() }, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    // Send a message to `id` from the loopback address `from`
+    async fn send_message(&self, from: Ipv4Addr, id: &str) -> Response {
+        let body = format!(r#"{{"id":"{}","message":"Hi"}}"#, id);
+        self.send_from(from, "POST", "/messaging", &[("Content-Type", "application/json")], body.as_bytes()).await
+    }
+}
+
+fn is_limited(response: &Response) -> bool {
+    response.status == 429
+}
+
+#[test]
+fn bucket_allows_a_burst_then_refills() {
+    let limiter = RateLimiter::new(RateLimits { per_minute: 60, burst: 3, cooldown: Duration::ZERO });
+    let now = Instant::now();
+
+    for _ in 0..3 {
+        assert_eq!(limiter.check_client(CLIENT, now), Ok(()));
+    }
+    assert_eq!(limiter.check_client(CLIENT, now), Err(Duration::from_secs(1)));
+
+    // One command per second comes back
+    assert_eq!(limiter.check_client(CLIENT, now + Duration::from_secs(1)), Ok(()));
+    assert!(limiter.check_client(CLIENT, now + Duration::from_secs(1)).is_err());
+}
+
+#[test]
+fn clients_have_their_own_buckets() {
+    let limiter = RateLimiter::new(RateLimits { per_minute: 60, burst: 1, cooldown: Duration::ZERO });
+    let now = Instant::now();
+
+    assert_eq!(limiter.check_client(CLIENT, now), Ok(()));
+    assert!(limiter.check_client(CLIENT, now).is_err());
+    assert_eq!(limiter.check_client(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 21)), now), Ok(()));
+}
+
+#[test]
+fn zero_per_minute_is_unlimited() {
+    let limiter = RateLimiter::new(RateLimits { per_minute: 0, burst: 1, cooldown: Duration::ZERO });
+    let now = Instant::now();
+
+    for _ in 0..100 {
+        assert_eq!(limiter.check_client(CLIENT, now), Ok(()));
+    }
+}
+
+// A command to `id` that the handler completes
+fn command(limiter: &RateLimiter, id: &str, at: Instant) -> Result<(), Duration> {
+    limiter.reserve_target(id, at).map(|_| ())
+}
+
+#[test]
+fn worker_cools_down_between_commands() {
+    let limiter = RateLimiter::new(RateLimits { cooldown: Duration::from_secs(5), ..RateLimits::default() });
+    let now = Instant::now();
+
+    assert_eq!(command(&limiter, "AA:AA:AA:AA:AA:AA", now), Ok(()));
+    assert_eq!(command(&limiter, "AA:AA:AA:AA:AA:AA", now + Duration::from_secs(2)), Err(Duration::from_secs(3)));
+    assert_eq!(command(&limiter, "BB:BB:BB:BB:BB:BB", now + Duration::from_secs(2)), Ok(()));
+    assert_eq!(command(&limiter, "AA:AA:AA:AA:AA:AA", now + Duration::from_secs(5)), Ok(()));
+}
+
+#[test]
+fn broadcast_counts_for_every_worker() {
+    let limiter = RateLimiter::new(RateLimits { cooldown: Duration::from_secs(5), ..RateLimits::default() });
+    let now = Instant::now();
+
+    assert_eq!(command(&limiter, "AA:AA:AA:AA:AA:AA", now), Ok(()));
+    assert_eq!(command(&limiter, "Broadcast", now + Duration::from_secs(1)), Err(Duration::from_secs(4)));
+    assert_eq!(command(&limiter, "Broadcast", now + Duration::from_secs(5)), Ok(()));
+    assert!(command(&limiter, "BB:BB:BB:BB:BB:BB", now + Duration::from_secs(6)).is_err());
+}
+
+#[test]
+fn commands_turned_down_give_the_cooldown_back() {
+    let limiter = RateLimiter::new(RateLimits::default());
+    let now = Instant::now();
+    let later = now + RateLimits::default().cooldown;
+
+    // Held from the moment the first is let through, the second can't slip past
+    let previous = limiter.reserve_target("AA:AA:AA:AA:AA:AA", now).unwrap();
+    assert!(limiter.reserve_target("AA:AA:AA:AA:AA:AA", now).is_err());
+    assert!(limiter.reserve_target("Broadcast", now).is_err());
+
+    limiter.release_target("AA:AA:AA:AA:AA:AA", now, previous);
+    let previous = limiter.reserve_target("AA:AA:AA:AA:AA:AA", now).unwrap();
+
+    // Given back once a later command has taken the cooldown, it stays with that one
+    assert!(limiter.reserve_target("AA:AA:AA:AA:AA:AA", later).is_ok());
+    limiter.release_target("AA:AA:AA:AA:AA:AA", now, previous);
+    assert!(limiter.reserve_target("AA:AA:AA:AA:AA:AA", later).is_err());
+}
+
+#[tokio::test]
+async fn flooding_client_gets_429() {
+    let server = start(RateLimits { per_minute: 6, burst: 2, cooldown: Duration::ZERO }).await;
+
+    assert!(!is_limited(&server.send_message(Ipv4Addr::LOCALHOST, "Broadcast").await));
+    assert!(!is_limited(&server.send_message(Ipv4Addr::LOCALHOST, "Broadcast").await));
+
+    let response = server.send_message(Ipv4Addr::LOCALHOST, "Broadcast").await;
+    assert!(is_limited(&response), "unexpected response: {}", response.head);
+    assert!(response.head.contains("retry-after: 10"), "unexpected response: {}", response.head);
+    assert_eq!(response.text(), r#"{"status":"RateLimited"}"#);
+
+    // Somebody else on the network is unaffected
+    assert!(!is_limited(&server.send_message(Ipv4Addr::new(127, 0, 0, 2), "Broadcast").await));
+}
+
+#[tokio::test]
+async fn worker_cooldown_applies_over_http() {
+    let server = start(RateLimits { cooldown: Duration::from_secs(60), ..RateLimits::default() }).await;
+
+    assert!(!is_limited(&server.send_message(Ipv4Addr::LOCALHOST, GEORGIA).await));
+    assert!(is_limited(&server.send_message(Ipv4Addr::new(127, 0, 0, 2), GEORGIA).await));
+    assert!(!is_limited(&server.send_message(Ipv4Addr::LOCALHOST, ASHER).await));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn commands_sent_at_once_share_one_cooldown() {
+    let server = std::sync::Arc::new(start(RateLimits { cooldown: Duration::from_secs(60), ..RateLimits::default() }).await);
+
+    // From clients of their own, all in flight together
+    let sends: Vec<_> = (1..=8).map(|i| {
+        let server = server.clone();
+        tokio::spawn(async move { server.send_message(Ipv4Addr::new(127, 0, 0, i), GEORGIA).await })
+    }).collect();
+
+    let mut delivered = 0;
+    for send in sends {
+        let response = send.await.unwrap();
+        if !is_limited(&response) {
+            assert_eq!(response.ok(), r#"{"status":"Complete"}"#);
+            delivered += 1;
+        }
+    }
+    assert_eq!(delivered, 1);
+}
+
+#[tokio::test]
+async fn refused_commands_do_not_cool_the_worker_down() {
+    let server = start(RateLimits { cooldown: Duration::from_secs(60), ..RateLimits::default() }).await;
+
+    // Nobody by that address is registered
+    assert_eq!(server.send_message(Ipv4Addr::LOCALHOST, "AA:AA:AA:AA:AA:AA").await.ok(), r#"{"status":"Unavailable"}"#);
+    assert!(!is_limited(&server.send_message(Ipv4Addr::LOCALHOST, "AA:AA:AA:AA:AA:AA").await));
+}
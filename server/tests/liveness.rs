@@ -0,0 +1,112 @@
+// Workers that stop answering are degraded, then offline, and only forgotten after a grace period.
+
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+use server::liveness::{Heartbeat, Liveness, LivenessPolicy};
+
+mod common;
+use common::{next_directive, wait_until, Setup, TestServer};
+
+async fn start(liveness: LivenessPolicy) -> TestServer {
+    TestServer::with(Setup { liveness, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    async fn register_online(&self, mac_address: &str, port: u16) {
+        self.register(mac_address, port).await;
+        self.wait_for_liveness(mac_address, Some(Liveness::Online)).await;
+    }
+
+    async fn send_message(&self, id: &str, message: &str) {
+        let receipt = self.post("/messaging", &format!(r#"{{"id":"{}","message":"{}"}}"#, id, message)).await;
+        assert_eq!(receipt, r#"{"status":"Complete"}"#);
+    }
+
+    fn liveness(&self, mac_address: &str) -> Option<Liveness> {
+        self.registry.read().get_worker(mac_address).map(|w| w.heartbeat.liveness)
+    }
+
+    async fn wait_for_liveness(&self, mac_address: &str, liveness: Option<Liveness>) {
+        let waiting_for = format!("{} to become {:?}", mac_address, liveness);
+        wait_until(&waiting_for, async || (self.liveness(mac_address) == liveness).then_some(())).await
+    }
+}
+
+#[test]
+fn heartbeat_degrades_then_goes_offline() {
+    let policy = LivenessPolicy { degraded_after: 1, offline_after: 3, retention: Duration::from_secs(60) };
+    let now = Instant::now();
+
+    let mut heartbeat = Heartbeat::new();
+    assert_eq!(heartbeat.liveness, Liveness::Offline);
+    assert_eq!(heartbeat.last_seen, None);
+
+    heartbeat.seen(now);
+    assert_eq!(heartbeat.liveness, Liveness::Online);
+
+    heartbeat.missed(&policy, now);
+    assert_eq!(heartbeat.liveness, Liveness::Degraded);
+    heartbeat.missed(&policy, now);
+    assert_eq!(heartbeat.liveness, Liveness::Degraded);
+    heartbeat.missed(&policy, now);
+    assert_eq!(heartbeat.liveness, Liveness::Offline);
+    assert_eq!(heartbeat.missed, 3);
+    assert_eq!(heartbeat.last_seen, Some(now));
+
+    heartbeat.seen(now + Duration::from_secs(1));
+    assert_eq!(heartbeat.liveness, Liveness::Online);
+    assert_eq!(heartbeat.missed, 0);
+}
+
+#[test]
+fn heartbeat_expires_after_retention_offline() {
+    let policy = LivenessPolicy { degraded_after: 1, offline_after: 1, retention: Duration::from_secs(60) };
+    let now = Instant::now();
+
+    let mut heartbeat = Heartbeat::new();
+    heartbeat.seen(now);
+    assert!(!heartbeat.expired(&policy, now + Duration::from_secs(120)));
+
+    heartbeat.missed(&policy, now);
+    assert_eq!(heartbeat.liveness, Liveness::Offline);
+
+    // Further misses don't restart the grace period
+    heartbeat.missed(&policy, now + Duration::from_secs(30));
+    assert!(!heartbeat.expired(&policy, now + Duration::from_secs(59)));
+    assert!(heartbeat.expired(&policy, now + Duration::from_secs(60)));
+}
+
+#[tokio::test]
+async fn worker_back_from_a_blip_keeps_its_command() {
+    let server = start(LivenessPolicy::default()).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    server.register_online("02:00:00:00:00:01", addr.port()).await;
+    server.send_message("02:00:00:00:00:01", "Still here").await;
+
+    drop(listener);
+    server.wait_for_liveness("02:00:00:00:00:01", Some(Liveness::Degraded)).await;
+    server.wait_for_liveness("02:00:00:00:00:01", Some(Liveness::Offline)).await;
+
+    // Back on the same address without registering again
+    let listener = TcpListener::bind(addr).await.unwrap();
+    assert_eq!(next_directive(&listener).await, "MESSAGE Still here");
+    server.wait_for_liveness("02:00:00:00:00:01", Some(Liveness::Online)).await;
+}
+
+#[tokio::test]
+async fn offline_worker_is_forgotten_after_retention() {
+    let server = start(LivenessPolicy { retention: Duration::from_millis(300), ..LivenessPolicy::default() }).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    server.register_online("02:00:00:00:00:02", listener.local_addr().unwrap().port()).await;
+    drop(listener);
+
+    server.wait_for_liveness("02:00:00:00:00:02", Some(Liveness::Offline)).await;
+    let offline = Instant::now();
+    server.wait_for_liveness("02:00:00:00:00:02", None).await;
+    assert!(offline.elapsed() >= Duration::from_millis(200), "forgotten too soon");
+}
@@ -0,0 +1,83 @@
+// What `/metrics` reports after registrations, commands and requests, and who may scrape it.
+
+use tokio::net::TcpListener;
+
+use server::auth::{AccessControl, Role};
+use server::settings::ServerConfig;
+
+mod common;
+use common::{next_directive, wait_until, Setup, TestServer};
+
+async fn start(access: AccessControl) -> TestServer {
+    TestServer::with(Setup { config: ServerConfig { access, ..common::config() }, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    // Scrape until every one of `lines` is in the metrics
+    async fn wait_for_metrics(&self, lines: &[&str]) -> String {
+        wait_until(&format!("{:?} in the metrics", lines), async || {
+            let metrics = self.request("GET", "/metrics", Some("Bearer none"), "").await.ok();
+            lines.iter().all(|l| metrics.lines().any(|m| m == *l)).then_some(metrics)
+        }).await
+    }
+}
+
+#[tokio::test]
+async fn fleet_activity_is_counted() {
+    let server = start(AccessControl::default()).await;
+
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register_line(&format!("REGISTER 02:00:00:00:00:01 {}", worker.local_addr().unwrap().port())).await;
+    server.register_line("HELLO").await;
+
+    // One that registers and is gone before anything reaches it
+    let gone = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+    server.register_line(&format!("REGISTER 02:00:00:00:00:02 {}", gone)).await;
+
+    server.wait_for_metrics(&[
+        r#"mb_registration_attempts_total{outcome="accepted"} 2"#,
+        r#"mb_registration_attempts_total{outcome="invalid"} 1"#,
+    ]).await;
+
+    server.request("POST", "/messaging", Some("Bearer none"), r#"{"id":"02:00:00:00:00:01","message":"Hello"}"#).await.ok();
+
+    // The worker takes whatever comes until the message arrives
+    wait_until("the message", async || (next_directive(&worker).await == "MESSAGE Hello").then_some(())).await;
+
+    let metrics = server.wait_for_metrics(&[
+        r#"mb_commands_total{type="message"} 1"#,
+        r#"mb_workers{liveness="online"} 1"#,
+        r#"mb_workers{liveness="offline"} 4"#,
+        r#"mb_http_requests_total{method="POST",route="/messaging",status="200"} 1"#,
+    ]).await;
+
+    // Every sweep sends the message again
+    assert!(metrics.contains(r#"mb_directives_sent_total{type="message"}"#), "unexpected metrics: {}", metrics);
+    assert!(metrics.contains(r#"mb_delivery_failures_total{reason="connect"}"#), "unexpected metrics: {}", metrics);
+    assert!(metrics.contains("mb_worker_connect_seconds_bucket{le=\"0.001\"}"), "unexpected metrics: {}", metrics);
+    assert!(metrics.contains("# TYPE mb_worker_connect_seconds histogram"), "unexpected metrics: {}", metrics);
+}
+
+#[tokio::test]
+async fn unmatched_paths_are_counted_together() {
+    let server = start(AccessControl::default()).await;
+
+    assert_eq!(server.request("GET", "/wp-admin", Some("Bearer none"), "").await.status, 404);
+    assert_eq!(server.request("GET", "/.env", Some("Bearer none"), "").await.status, 404);
+
+    let metrics = server.wait_for_metrics(&[r#"mb_http_requests_total{method="GET",route="unmatched",status="404"} 2"#]).await;
+    assert!(!metrics.contains("wp-admin"), "unexpected metrics: {}", metrics);
+}
+
+#[tokio::test]
+async fn scrapers_need_a_viewer_token() {
+    let mut access = AccessControl::default();
+    access.add_token("prometheus", Role::Viewer);
+    let server = start(access).await;
+
+    assert_eq!(server.request("GET", "/metrics", Some("Bearer wrong"), "").await.status, 401);
+
+    let metrics = server.request("GET", "/metrics", Some("Bearer prometheus"), "").await.ok();
+    assert!(metrics.contains(r#"mb_http_requests_total{method="GET",route="/metrics",status="401"} 1"#), "unexpected metrics: {}", metrics);
+}
@@ -0,0 +1,197 @@
+// Firmware images kept by the server, and updates pushed to workers that download them.
+
+use std::path::PathBuf;
+
+use tokio::net::TcpListener;
+
+use server::events::EventKind;
+use server::firmware::{FirmwareStore, UploadError};
+use server::registry::Registry;
+use telemetry::Telemetry;
+
+mod common;
+use common::{next_directive, wait_until, Setup, TestServer};
+
+const RELEASE_KEY: [u8; ota::KEY_LEN] = [7; ota::KEY_LEN];
+
+fn image() -> Vec<u8> {
+    (0..5000u32).map(|i| (i % 251) as u8).collect()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mb-firmware-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+#[test]
+fn images_survive_a_restart() {
+    let dir = temp_dir("restart");
+
+    let store = FirmwareStore::open(&dir, None).unwrap();
+    let info = store.add("0.2.0", image(), None).unwrap();
+    assert_eq!(info.size, 5000);
+    assert_eq!(info.sha256, ota::to_hex(&ota::digest(&image())));
+
+    let store = FirmwareStore::open(&dir, None).unwrap();
+    let image_again = store.get("0.2.0").unwrap();
+    assert_eq!(*image_again.data, image());
+    assert_eq!(store.list().len(), 1);
+
+    // Damaged images aren't served
+    std::fs::write(dir.join("0.2.0.bin"), b"not the image").unwrap();
+    assert!(FirmwareStore::open(&dir, None).unwrap().get("0.2.0").is_none());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn images_the_release_key_wont_take_are_not_picked_up() {
+    let dir = temp_dir("release-key");
+    let release_key = ota::public_key(&RELEASE_KEY);
+
+    // Taken before a release key was configured, or under another one
+    FirmwareStore::open(&dir, None).unwrap().add("0.1.0", image(), None).unwrap();
+    let other_key = [8; ota::KEY_LEN];
+    FirmwareStore::open(&dir, Some(ota::public_key(&other_key))).unwrap()
+        .add("0.1.1", image(), Some(ota::sign(&other_key, &ota::digest(&image())))).unwrap();
+    FirmwareStore::open(&dir, Some(release_key)).unwrap()
+        .add("0.2.0", image(), Some(ota::sign(&RELEASE_KEY, &ota::digest(&image())))).unwrap();
+
+    let versions: Vec<String> = FirmwareStore::open(&dir, Some(release_key)).unwrap().list().into_iter().map(|i| i.version).collect();
+    assert_eq!(versions, ["0.2.0"]);
+    assert_eq!(FirmwareStore::open(&dir, None).unwrap().list().len(), 3);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn uploads_are_checked() {
+    let store = FirmwareStore::in_memory(Some(ota::public_key(&RELEASE_KEY)));
+    let signature = ota::sign(&RELEASE_KEY, &ota::digest(&image()));
+
+    assert_eq!(store.add("../0.2.0", image(), Some(signature)).err(), Some(UploadError::InvalidVersion));
+    assert_eq!(store.add("0.2.0", image(), None).err(), Some(UploadError::Unsigned));
+    assert_eq!(store.add("0.2.0", image(), Some(ota::sign(&[8; ota::KEY_LEN], &ota::digest(&image())))).err(), Some(UploadError::Signature));
+    assert!(store.get("0.2.0").is_none());
+
+    let info = store.add("0.2.0", image(), Some(signature)).unwrap();
+    assert_eq!(info.signature, Some(ota::to_hex(&signature)));
+}
+
+async fn start() -> TestServer {
+    let registry = Registry::default().with_firmware(FirmwareStore::in_memory(Some(ota::public_key(&RELEASE_KEY))));
+    TestServer::with(Setup { registry, ..Setup::default() }).await
+}
+
+impl TestServer {
+
+    async fn upload(&self, version: &str, signature: &[u8; ota::SIGNATURE_LEN]) -> String {
+        let path = format!("/firmware?version={}&signature={}", version, ota::to_hex(signature));
+        self.send("POST", &path, &[("Content-Type", "application/octet-stream")], &image()).await.ok()
+    }
+
+    async fn register_running(&self, mac_address: &str, port: u16, firmware: &str) {
+        let telemetry = Telemetry { firmware: Some(firmware.to_string()), ..Telemetry::default() };
+        self.register_with(mac_address, port, Some(&telemetry)).await;
+    }
+}
+
+// The first directive that is an update, answering the pings before it
+async fn receive_update(worker: &TcpListener) -> ota::Update {
+    loop {
+        if let Some(update) = ota::Update::parse(&next_directive(worker).await) {
+            return update;
+        }
+    }
+}
+
+#[tokio::test]
+async fn images_are_uploaded_listed_and_served() {
+    let server = start().await;
+    let signature = ota::sign(&RELEASE_KEY, &ota::digest(&image()));
+
+    assert_eq!(server.upload("0.2.0", &[0; ota::SIGNATURE_LEN]).await, r#"{"status":"Rejected"}"#);
+    assert_eq!(server.upload("0.2.0", &signature).await, r#"{"status":"Complete"}"#);
+
+    let listed = server.get("/api/firmware").await;
+    assert!(listed.starts_with(r#"[{"version":"0.2.0","size":5000,"sha256":""#), "unexpected listing: {}", listed);
+
+    let response = server.send("GET", "/firmware/0.2.0", &[], b"").await;
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, image());
+
+    assert_eq!(server.send("GET", "/firmware/0.3.0", &[], b"").await.status, 404);
+}
+
+#[tokio::test]
+async fn workers_are_judged_by_the_firmware_they_come_back_with() {
+    let server = start().await;
+    server.upload("0.2.0", &ota::sign(&RELEASE_KEY, &ota::digest(&image()))).await;
+
+    let updated = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let failed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register_running("02:00:00:00:00:01", updated.local_addr().unwrap().port(), "0.1.0").await;
+    server.register_running("02:00:00:00:00:02", failed.local_addr().unwrap().port(), "0.1.0").await;
+
+    assert_eq!(server.post("/ota", r#"{"id":"Broadcast","version":"0.3.0"}"#).await, r#"{"status":"Unavailable"}"#);
+    assert_eq!(server.post("/ota", r#"{"id":"Broadcast","version":"0.2.0"}"#).await, r#"{"status":"Complete"}"#);
+
+    // The directive points at the image on the portal, and the image passes
+    let update = receive_update(&updated).await;
+    assert_eq!(update.url, format!("http://{}/firmware/0.2.0", server.http));
+    let image = server.send("GET", &update.url[update.url.find("/firmware").unwrap()..], &[], b"").await;
+    let mut verifier = ota::Verifier::new(&update);
+    verifier.update(&image.body).unwrap();
+    assert_eq!(verifier.finish(Some(&ota::public_key(&RELEASE_KEY))), Ok(()));
+
+    receive_update(&failed).await;
+
+    wait_until("the updates to be noted as sent", async || {
+        (server.get("/api/workers").await.matches(r#""updating_to":"0.2.0""#).count() >= 2).then_some(())
+    }).await;
+
+    // Back from the reboot, one running the new image, one the old
+    server.register_running("02:00:00:00:00:01", updated.local_addr().unwrap().port(), "0.2.0").await;
+    server.register_running("02:00:00:00:00:02", failed.local_addr().unwrap().port(), "0.1.0").await;
+
+    let event = server.wait_for_event(|e| matches!(e.kind, EventKind::Updated { .. })).await;
+    assert_eq!(event.kind, EventKind::Updated { worker: "02:00:00:00:00:01".to_string(), version: "0.2.0".to_string() });
+
+    let event = server.wait_for_event(|e| matches!(e.kind, EventKind::UpdateFailed { .. })).await;
+    assert_eq!(event.kind, EventKind::UpdateFailed { worker: "02:00:00:00:00:02".to_string(), version: "0.2.0".to_string(), running: Some("0.1.0".to_string()) });
+
+    // Updates go out once, the workers are back to pings
+    let directive = next_directive(&updated).await;
+    assert!(ota::Update::parse(&directive).is_none(), "update sent again: {}", directive);
+
+    let metrics = server.get("/metrics").await;
+    assert!(metrics.contains(r#"mb_firmware_updates_total{outcome="updated"} 1"#), "unexpected metrics: {}", metrics);
+    assert!(metrics.contains(r#"mb_firmware_updates_total{outcome="failed"} 1"#), "unexpected metrics: {}", metrics);
+}
+
+#[tokio::test]
+async fn workers_already_running_the_version_are_left_alone() {
+    let server = start().await;
+    server.upload("0.2.0", &ota::sign(&RELEASE_KEY, &ota::digest(&image()))).await;
+
+    let current = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let behind = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register_running("02:00:00:00:00:01", current.local_addr().unwrap().port(), "0.2.0").await;
+    server.register_running("02:00:00:00:00:02", behind.local_addr().unwrap().port(), "0.1.0").await;
+
+    assert_eq!(server.post("/ota", r#"{"id":"02:00:00:00:00:01","version":"0.2.0"}"#).await, r#"{"status":"Current"}"#);
+    assert_eq!(server.post("/ota", r#"{"id":"Broadcast","version":"0.2.0"}"#).await, r#"{"status":"Complete"}"#);
+
+    assert_eq!(receive_update(&behind).await.version, "0.2.0");
+    wait_until("the update to be noted as sent", async || {
+        server.get("/api/workers").await.contains(r#""updating_to":"0.2.0""#).then_some(())
+    }).await;
+
+    // The one that has it only hears pings
+    for _ in 0..3 {
+        let directive = next_directive(&current).await;
+        assert!(ota::Update::parse(&directive).is_none(), "update sent anyway: {}", directive);
+    }
+    assert_eq!(server.get("/api/workers").await.matches(r#""updating_to":"0.2.0""#).count(), 1);
+}
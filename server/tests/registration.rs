@@ -0,0 +1,208 @@
+// The registration exchange against the real listener, with and without pre-shared keys.
+
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use server::MicroManager;
+use server::liveness::LivenessPolicy;
+use server::registry::Registry;
+use server::settings::ServerConfig;
+use telemetry::Telemetry;
+
+mod common;
+use common::{wait_until, GEORGIA};
+
+const KEY: [u8; 16] = *b"0123456789abcdef";
+
+async fn start(open_registration: bool) -> (SocketAddr, Registry) {
+    let mut micro_manager = MicroManager::new();
+    micro_manager.set_key(GEORGIA, KEY.to_vec());
+    let registry = Registry::new(micro_manager);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server::registration_loop(registry.clone(), listener, config::BROADCAST_PORT, open_registration));
+
+    (addr, registry)
+}
+
+// Go through the exchange like a worker holding `key` and return the server's verdict
+async fn register(addr: SocketAddr, mac_address: &str, key: Option<&[u8]>) -> String {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(registration::request(mac_address, Some(4000)).as_bytes()).await.unwrap();
+
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+
+    if let Some(nonce) = registration::parse_challenge(&line) {
+        let response = match key {
+            Some(key) => registration::response(key, &nonce, mac_address),
+            None => "RESPONSE 00\n".to_string(),
+        };
+        writer.write_all(response.as_bytes()).await.unwrap();
+        line.clear();
+        reader.read_line(&mut line).await.unwrap();
+    }
+
+    line.trim().to_string()
+}
+
+fn is_active(registry: &Registry, mac_address: &str) -> Option<bool> {
+    registry.read().get_worker(mac_address).map(|w| w.active)
+}
+
+#[tokio::test]
+async fn worker_with_the_right_key_is_accepted() {
+    let (addr, registry) = start(true).await;
+
+    assert_eq!(register(addr, GEORGIA, Some(&KEY)).await, registration::ACCEPTED);
+    assert_eq!(is_active(&registry, GEORGIA), Some(true));
+}
+
+#[tokio::test]
+async fn spoofed_mac_cannot_take_a_keyed_slot() {
+    let (addr, registry) = start(true).await;
+
+    assert_eq!(register(addr, GEORGIA, Some(b"fedcba9876543210")).await, registration::REJECTED);
+    assert_eq!(register(addr, GEORGIA, None).await, registration::REJECTED);
+    assert_eq!(is_active(&registry, GEORGIA), Some(false));
+}
+
+#[tokio::test]
+async fn response_is_bound_to_the_mac_address() {
+    let (addr, _) = start(true).await;
+
+    // A valid key used for another worker's address
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(registration::request(GEORGIA, None).as_bytes()).await.unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    let nonce = registration::parse_challenge(&line).unwrap();
+
+    writer.write_all(registration::response(&KEY, &nonce, "02:00:00:00:00:01").as_bytes()).await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+
+    assert_eq!(line.trim(), registration::REJECTED);
+}
+
+#[tokio::test]
+async fn open_registration_accepts_workers_without_a_key() {
+    let (addr, registry) = start(true).await;
+
+    assert_eq!(register(addr, "02:00:00:00:00:01", None).await, registration::ACCEPTED);
+    assert_eq!(is_active(&registry, "02:00:00:00:00:01"), Some(true));
+}
+
+#[tokio::test]
+async fn closed_registration_rejects_workers_without_a_key() {
+    // Unless the operator opts in
+    let (addr, registry) = start(ServerConfig::default().open_registration).await;
+
+    assert_eq!(register(addr, "02:00:00:00:00:01", None).await, registration::REJECTED);
+    assert_eq!(is_active(&registry, "02:00:00:00:00:01"), None);
+
+    assert_eq!(register(addr, GEORGIA, Some(&KEY)).await, registration::ACCEPTED);
+}
+
+#[tokio::test]
+async fn keys_are_managed_through_the_registry() {
+    let (addr, registry) = start(false).await;
+
+    registry.update(|m| m.set_key("02:00:00:00:00:01", KEY.to_vec()));
+    assert_eq!(register(addr, "02:00:00:00:00:01", Some(&KEY)).await, registration::ACCEPTED);
+
+    registry.update(|m| m.remove_key("02:00:00:00:00:01"));
+    assert_eq!(register(addr, "02:00:00:00:00:01", Some(&KEY)).await, registration::REJECTED);
+}
+
+#[tokio::test]
+async fn keyed_worker_only_gets_sealed_directives() {
+    let (addr, registry) = start(true).await;
+    tokio::spawn(server::broadcast_loop(registry.clone(), tokio::time::Duration::from_millis(50), tokio::time::Duration::from_millis(500), LivenessPolicy::default()));
+
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = worker.local_addr().unwrap().port();
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(registration::request(GEORGIA, Some(port)).as_bytes()).await.unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    let nonce = registration::parse_challenge(&line).unwrap();
+    writer.write_all(registration::response(&KEY, &nonce, GEORGIA).as_bytes()).await.unwrap();
+
+    let mut session = registration::Session::new(&KEY, &nonce, GEORGIA);
+    // The session of an earlier registration
+    let mut other = registration::Session::new(&KEY, &[0u8; registration::NONCE_LEN], GEORGIA);
+
+    for _ in 0..2 {
+        let (mut socket, _) = worker.accept().await.unwrap();
+        let mut sealed = String::new();
+        socket.read_to_string(&mut sealed).await.unwrap();
+
+        assert!(sealed.starts_with("SEALED "), "unexpected directive {}", sealed);
+        assert_eq!(other.open(&sealed), None);
+        assert_eq!(session.open(&sealed).as_deref(), Some("PING"));
+
+        // Replays are refused
+        assert_eq!(session.open(&sealed), None);
+    }
+}
+
+#[tokio::test]
+async fn keyed_worker_replies_are_only_taken_sealed() {
+    let (addr, registry) = start(true).await;
+    tokio::spawn(server::broadcast_loop(registry.clone(), tokio::time::Duration::from_millis(50), tokio::time::Duration::from_millis(500), LivenessPolicy::default()));
+
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = worker.local_addr().unwrap().port();
+    let report = |uptime| Telemetry { uptime: Some(uptime), ..Telemetry::default() }.line();
+    let uptime = || registry.read().get_worker(GEORGIA).and_then(|w| w.telemetry.as_ref()?.uptime);
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    writer.write_all(registration::request(GEORGIA, Some(port)).as_bytes()).await.unwrap();
+    let mut line = String::new();
+    reader.read_line(&mut line).await.unwrap();
+    let nonce = registration::parse_challenge(&line).unwrap();
+    writer.write_all(registration::response(&KEY, &nonce, GEORGIA).as_bytes()).await.unwrap();
+    line.clear();
+    reader.read_line(&mut line).await.unwrap();
+    assert_eq!(line.trim(), registration::ACCEPTED);
+
+    let mut session = registration::Session::new(&KEY, &nonce, GEORGIA);
+    let first = session.seal_reply(&report(1));
+    writer.write_all(first.as_bytes()).await.unwrap();
+    drop(writer);
+
+    // Plaintext, and a replay of the first report, answering directives
+    for reply in [report(2), first] {
+        let (mut socket, _) = worker.accept().await.unwrap();
+        let mut directive = String::new();
+        socket.read_to_string(&mut directive).await.unwrap();
+        socket.write_all(reply.as_bytes()).await.unwrap();
+    }
+
+    let (mut socket, _) = worker.accept().await.unwrap();
+    let mut directive = String::new();
+    socket.read_to_string(&mut directive).await.unwrap();
+    assert_eq!(uptime(), Some(1));
+
+    socket.write_all(session.seal_reply(&report(3)).as_bytes()).await.unwrap();
+    drop(socket);
+
+    wait_until("the sealed report to be taken", async || (uptime() == Some(3)).then_some(())).await;
+}
@@ -0,0 +1,54 @@
+// The shared registry: lookups, listing order and change notifications.
+
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+use server::MicroManager;
+use server::registry::Registry;
+
+mod common;
+use common::{next_directive, Setup, TestServer, GEORGIA};
+
+#[test]
+fn persistent_workers_are_listed_and_looked_up() {
+    let registry = Registry::default();
+    let micro_manager = registry.read();
+
+    let first = micro_manager.workers().next().unwrap();
+    assert!(first.persistent);
+    assert!(micro_manager.workers().all(|w| w.persistent));
+
+    assert_eq!(micro_manager.get_worker(GEORGIA).unwrap().mac_address, GEORGIA);
+    assert!(micro_manager.get_worker("02:00:00:00:00:01").is_none());
+}
+
+#[test]
+fn updates_notify_and_records_do_not() {
+    let registry = Registry::new(MicroManager::new());
+    let changes = registry.subscribe();
+
+    registry.record(|m| m.set_key(GEORGIA, vec![0; 16]));
+    assert!(!changes.has_changed().unwrap());
+
+    registry.update(|m| m.remove_key(GEORGIA));
+    assert!(changes.has_changed().unwrap());
+    assert_eq!(registry.revision(), 1);
+}
+
+#[tokio::test]
+async fn commands_go_out_without_waiting_for_the_next_sweep() {
+    // Far longer than the test is willing to wait
+    let server = TestServer::with(Setup { sweep_interval: Duration::from_secs(30), ..Setup::default() }).await;
+
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    server.register("02:00:00:00:00:01", worker.local_addr().unwrap().port()).await;
+
+    // Registering is a change too, so the worker is pinged straight away
+    assert_eq!(next_directive(&worker).await, "PING");
+
+    let sent = Instant::now();
+    server.post("/messaging", r#"{"id":"02:00:00:00:00:01","message":"Now"}"#).await;
+
+    assert_eq!(next_directive(&worker).await, "MESSAGE Now");
+    assert!(sent.elapsed() < Duration::from_secs(2));
+}
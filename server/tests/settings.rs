@@ -0,0 +1,110 @@
+// Where settings come from: the config file, `MB_*` variables and flags, in that order.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tokio::time::Duration;
+
+use server::settings::ServerConfig;
+
+// Write `contents` to a scratch config file
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("mb-config-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn load(env: &[(&str, &str)], args: &[&str]) -> Result<ServerConfig, String> {
+    ServerConfig::from_sources(
+        env.iter().map(|(k, v)| (k.to_string(), v.to_string())),
+        args.iter().map(|a| a.to_string()),
+    )
+}
+
+fn http_addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+#[test]
+fn flags_beat_the_environment_which_beats_the_file() {
+    let path = config_file("precedence", "http_addr = \"127.0.0.1:1001\"\nworker_port = 1002\nsweep_interval_ms = 1003\n");
+    let path = path.to_str().unwrap();
+
+    let server_config = load(
+        &[("MB_WORKER_PORT", "2002"), ("MB_SWEEP_INTERVAL_MS", "2003")],
+        &["--config", path, "--sweep-interval-ms", "3003"],
+    ).unwrap();
+
+    assert_eq!(server_config.http_addr, http_addr(1001));
+    assert_eq!(server_config.worker_port, 2002);
+    assert_eq!(server_config.sweep_interval, Duration::from_millis(3003));
+
+    // What nobody sets keeps its default
+    assert_eq!(server_config.connect_timeout, ServerConfig::default().connect_timeout);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn the_file_is_named_by_flag_or_environment() {
+    let from_env = config_file("from-env", "worker_port = 1001\n");
+    let from_flag = config_file("from-flag", "worker_port = 1002\n");
+    let env = [("MB_CONFIG", from_env.to_str().unwrap())];
+
+    assert_eq!(load(&env, &[]).unwrap().worker_port, 1001);
+    assert_eq!(load(&env, &["--config", from_flag.to_str().unwrap()]).unwrap().worker_port, 1002);
+
+    // Without either there is no file to read
+    assert_eq!(load(&[], &[]).unwrap().worker_port, ServerConfig::default().worker_port);
+
+    let missing = load(&[("MB_CONFIG", "/nonexistent/mb.toml")], &[]).unwrap_err();
+    assert!(missing.starts_with("Unable to read /nonexistent/mb.toml"), "unexpected error: {}", missing);
+
+    std::fs::remove_file(from_env).unwrap();
+    std::fs::remove_file(from_flag).unwrap();
+}
+
+#[test]
+fn unknown_keys_are_refused() {
+    let path = config_file("unknown", "worker_prot = 1001\n");
+
+    let error = load(&[], &["--config", path.to_str().unwrap()]).unwrap_err();
+    assert!(error.contains("unknown field `worker_prot`"), "unexpected error: {}", error);
+    assert_eq!(load(&[], &["--worker-prot", "1001"]).unwrap_err(), "--worker-prot: unknown setting worker_prot");
+    assert_eq!(load(&[], &["worker-port"]).unwrap_err(), "Unexpected argument worker-port");
+
+    // Other MB_ variables may belong to somebody else
+    assert!(load(&[("MB_WORKER_PROT", "1001")], &[]).is_ok());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn bad_values_are_refused() {
+    assert_eq!(load(&[("MB_WORKER_PORT", "lots")], &[]).unwrap_err(), "MB_WORKER_PORT: invalid value 'lots': invalid digit found in string");
+    assert_eq!(load(&[], &["--worker-port"]).unwrap_err(), "Missing value for --worker-port");
+    assert_eq!(load(&[], &["--worker-port", "--sweep-interval-ms", "10"]).unwrap_err(), "Missing value for --worker-port");
+}
+
+#[test]
+fn open_registration_is_a_switch() {
+    assert!(!load(&[], &[]).unwrap().open_registration);
+    assert!(load(&[], &["--open-registration"]).unwrap().open_registration);
+    assert!(!load(&[], &["--open-registration", "false"]).unwrap().open_registration);
+
+    // Followed by another flag, which keeps its value
+    let server_config = load(&[], &["--open-registration", "--http-addr", "127.0.0.1:1001"]).unwrap();
+    assert!(server_config.open_registration);
+    assert_eq!(server_config.http_addr, http_addr(1001));
+}
+
+#[test]
+fn tls_needs_both_certificate_and_key() {
+    let error = "tls_cert and tls_key have to be given together";
+    assert_eq!(load(&[("MB_TLS_CERT", "cert.pem")], &[]).unwrap_err(), error);
+    assert_eq!(load(&[], &["--tls-key", "key.pem"]).unwrap_err(), error);
+
+    let server_config = load(&[("MB_TLS_CERT", "cert.pem")], &["--tls-key", "key.pem"]).unwrap();
+    assert_eq!(server_config.tls_cert, Some(PathBuf::from("cert.pem")));
+    assert_eq!(server_config.tls_key, Some(PathBuf::from("key.pem")));
+}
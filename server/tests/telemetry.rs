@@ -0,0 +1,92 @@
+// Worker reports on registration and in answer to directives, and how they are listed.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use telemetry::{LogLine, Telemetry};
+
+mod common;
+use common::{wait_until, TestServer, WAIT};
+
+impl TestServer {
+
+    async fn wait_for_telemetry(&self, mac_address: &str) -> Telemetry {
+        let waiting_for = format!("telemetry from {}", mac_address);
+        wait_until(&waiting_for, async || self.registry.read().get_worker(mac_address).and_then(|w| w.telemetry.clone())).await
+    }
+}
+
+fn report(uptime: u64) -> Telemetry {
+    Telemetry {
+        rssi: Some(-58),
+        uptime: Some(uptime),
+        reset_reason: Some("poweron".to_string()),
+        free_heap: Some(120_000),
+        firmware: Some("0.1.0".to_string()),
+        display: Some("ok".to_string()),
+    }
+}
+
+#[tokio::test]
+async fn report_follows_registration() {
+    let server = TestServer::start().await;
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    server.register_with("02:00:00:00:00:01", worker.local_addr().unwrap().port(), Some(&report(12))).await;
+
+    assert_eq!(server.wait_for_telemetry("02:00:00:00:00:01").await, report(12));
+}
+
+#[tokio::test]
+async fn workers_answer_directives_with_reports() {
+    let server = TestServer::start().await;
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    // An older worker, silent on registration
+    server.register_with("02:00:00:00:00:02", worker.local_addr().unwrap().port(), None).await;
+
+    let (mut socket, _) = tokio::time::timeout(WAIT, worker.accept()).await.unwrap().unwrap();
+    let mut directive = String::new();
+    socket.read_to_string(&mut directive).await.unwrap();
+    assert_eq!(directive, "PING");
+    socket.write_all(report(3600).line().as_bytes()).await.unwrap();
+    drop(socket);
+
+    assert_eq!(server.wait_for_telemetry("02:00:00:00:00:02").await, report(3600));
+}
+
+#[tokio::test]
+async fn forwarded_log_lines_come_with_the_report() {
+    let server = TestServer::start().await;
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    server.register_with("02:00:00:00:00:04", worker.local_addr().unwrap().port(), None).await;
+
+    let (mut socket, _) = tokio::time::timeout(WAIT, worker.accept()).await.unwrap().unwrap();
+    let mut directive = String::new();
+    socket.read_to_string(&mut directive).await.unwrap();
+
+    // Lines in any order around the report, and ones the server doesn't know are skipped
+    let log = |level: &str, message: &str| LogLine { level: level.to_string(), message: message.to_string() }.line();
+    let reply = log("warn", "Display not found") + &report(60).line() + "SOMETHING new\n" + &log("info", "Connected");
+    socket.write_all(reply.as_bytes()).await.unwrap();
+    drop(socket);
+
+    assert_eq!(server.wait_for_telemetry("02:00:00:00:00:04").await, report(60));
+}
+
+#[tokio::test]
+async fn workers_are_listed_with_their_telemetry() {
+    let server = TestServer::start().await;
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    server.register_with("02:00:00:00:00:03", worker.local_addr().unwrap().port(), Some(&report(90))).await;
+    server.wait_for_telemetry("02:00:00:00:00:03").await;
+
+    let workers = server.get("/api/workers").await;
+    assert!(workers.contains(r#""mac_address":"02:00:00:00:00:03""#), "unexpected listing: {}", workers);
+    assert!(workers.contains(r#""rssi":-58,"uptime_s":90,"reset_reason":"poweron","free_heap":120000,"firmware":"0.1.0","display":"ok""#), "unexpected listing: {}", workers);
+
+    // Persistent workers that never registered have nothing to report
+    assert!(workers.contains(r#""alias":"Georgia","ip_address":null,"active":false,"persistent":true,"liveness":"offline","last_seen_s":null,"missed_sweeps":0,"telemetry":null"#), "unexpected listing: {}", workers);
+}
@@ -0,0 +1,80 @@
+// The portal over HTTPS with a freshly generated self-signed certificate.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use rustls::pki_types::{CertificateDer, ServerName};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_rustls::TlsConnector;
+
+use server::registry::Registry;
+use server::settings::ServerConfig;
+
+// Write a certificate for localhost and its key to a scratch directory
+fn self_signed(name: &str) -> (PathBuf, PathBuf, CertificateDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("mb-tls-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let cert = dir.join("cert.pem");
+    let key = dir.join("key.pem");
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+
+    (cert, key, certified.cert.der().clone())
+}
+
+#[tokio::test]
+async fn portal_is_served_over_https() {
+    let (cert, key, der) = self_signed("portal");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let app = server::app(Registry::default(), &ServerConfig::default());
+    tokio::spawn(async move { server::serve_tls(listener, app, &cert, &key).await.unwrap() });
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(der).unwrap();
+    let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions().unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let mut tls = TlsConnector::from(Arc::new(client_config))
+        .connect(ServerName::try_from("localhost").unwrap(), tcp).await.unwrap();
+
+    tls.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+
+    let mut response = Vec::new();
+    // The server may close without a TLS close_notify, which is fine once the body is in
+    let _ = timeout(Duration::from_secs(5), tls.read_to_end(&mut response)).await.unwrap();
+    let response = String::from_utf8_lossy(&response);
+
+    assert!(response.starts_with("HTTP/1.1 200"), "unexpected response: {}", response);
+    assert!(response.contains("MB Control"));
+}
+
+#[tokio::test]
+async fn plaintext_is_refused_on_the_https_port() {
+    let (cert, key, _) = self_signed("plaintext");
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let app = server::app(Registry::default(), &ServerConfig::default());
+    tokio::spawn(async move { server::serve_tls(listener, app, &cert, &key).await.unwrap() });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await.unwrap();
+
+    let mut response = Vec::new();
+    let _ = timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await.unwrap();
+
+    assert!(!String::from_utf8_lossy(&response).contains("MB Control"));
+}
@@ -0,0 +1,71 @@
+// Lines workers forward with their replies, as kept by the server and served per worker.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+use server::worker_logs::WorkerLogs;
+use telemetry::LogLine;
+
+mod common;
+use common::{wait_until, TestServer, WAIT};
+
+fn log(level: &str, message: &str) -> LogLine {
+    LogLine { level: level.to_string(), message: message.to_string() }
+}
+
+#[test]
+fn only_the_latest_lines_are_kept() {
+    let logs = WorkerLogs::new(2);
+
+    logs.record("02:00:00:00:00:01", log("info", "one"));
+    logs.record("02:00:00:00:00:01", log("warn", "two"));
+    logs.record("02:00:00:00:00:01", log("error", "three"));
+    logs.record("02:00:00:00:00:02", log("info", "elsewhere"));
+
+    let messages: Vec<String> = logs.lines("02:00:00:00:00:01").into_iter().map(|l| l.message).collect();
+    assert_eq!(messages, ["two", "three"]);
+
+    logs.forget("02:00:00:00:00:01");
+    assert!(logs.lines("02:00:00:00:00:01").is_empty());
+    assert_eq!(logs.lines("02:00:00:00:00:02").len(), 1);
+}
+
+#[test]
+fn nothing_is_kept_without_room() {
+    let logs = WorkerLogs::new(0);
+
+    logs.record("02:00:00:00:00:01", log("info", "one"));
+    assert!(logs.lines("02:00:00:00:00:01").is_empty());
+}
+
+#[tokio::test]
+async fn forwarded_lines_are_served_per_worker() {
+    let server = TestServer::start().await;
+    let worker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    server.register("02:00:00:00:00:01", worker.local_addr().unwrap().port()).await;
+
+    let (mut socket, _) = timeout(WAIT, worker.accept()).await.unwrap().unwrap();
+    let mut directive = String::new();
+    socket.read_to_string(&mut directive).await.unwrap();
+    let reply = log("warn", "Display not found").line() + &log("info", "Connected to \"Classroom\"").line();
+    socket.write_all(reply.as_bytes()).await.unwrap();
+    drop(socket);
+
+    let logs = wait_until("the lines to be kept", async || {
+        Some(server.get("/api/workers/02:00:00:00:00:01/logs").await).filter(|logs| logs.contains("Connected"))
+    }).await;
+
+    assert!(logs.starts_with(r#"[{"time":"#), "unexpected logs: {}", logs);
+    assert!(logs.contains(r#""level":"warn","message":"Display not found"}"#), "unexpected logs: {}", logs);
+    assert!(logs.find("Display not found").unwrap() < logs.find("Connected").unwrap(), "unexpected logs: {}", logs);
+
+    // Escaped the way the portal asks for them
+    assert_eq!(server.get("/api/workers/02%3A00%3A00%3A00%3A00%3A01/logs").await, logs);
+    assert_eq!(server.get("/api/workers/02:00:00:00:00:09/logs").await, "[]");
+
+    // Removed workers take their lines with them
+    server.post("/registry/remove", r#"{"id":"02:00:00:00:00:01"}"#).await;
+    assert_eq!(server.get("/api/workers/02:00:00:00:00:01/logs").await, "[]");
+}
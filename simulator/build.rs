@@ -0,0 +1,9 @@
+use std::path::Path;
+
+// The client's sprite sheets, encoded the way its build does it so the simulator plays the same
+// bytes a worker has in flash.
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=../client/media");
+    sprite::encode_sheets(Path::new("../client/media"), Path::new(&out_dir)).unwrap();
+}
@@ -0,0 +1,128 @@
+//! Host-side stand-in for the worker's SSD1306, so screens can be developed and checked on Linux.
+//!
+//! Screens are rendered by the same `render` code as the client, into a [`Frame`] which has the
+//! exact memory layout of the display. Animations are played from the same encoded sprites through
+//! the same [`SpriteDecoder`], so a frame is what the client blits.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use render::Screen;
+use sprite::codec::SpriteDecoder;
+use sprite::Frame;
+
+pub const DISPLAY_WIDTH: usize = 128;
+pub const DISPLAY_HEIGHT: usize = 64;
+
+/// The animations known to the client, encoded from the same sprite sheets at build time.
+pub struct Animations {
+    encoded: Vec<(&'static str, &'static [u8])>,
+}
+
+impl Animations {
+
+    pub fn new() -> Self {
+        Animations {
+            encoded: vec![
+                ("CartoonEyes", include_bytes!(concat!(env!("OUT_DIR"), "/eyes.mbs"))),
+                ("Heart",       include_bytes!(concat!(env!("OUT_DIR"), "/heart.mbs"))),
+                ("Unicorn",     include_bytes!(concat!(env!("OUT_DIR"), "/unicorn.mbs"))),
+            ],
+        }
+    }
+
+    /// The encoded animation, as the client embeds it.
+    pub fn get(&self, name: &str) -> Option<&'static [u8]> {
+        self.encoded.iter().find(|(n, _)| *n == name).map(|(_, e)| *e)
+    }
+
+    /// Frame `frame_index` of the animation, wrapping around like the client's playback loop.
+    /// Frames are decoded in order up to it, the way the client plays them.
+    pub fn frame(&self, name: &str, frame_index: usize) -> Result<Frame> {
+        let Some(encoded) = self.get(name) else {
+            bail!("Unknown animation {}", name);
+        };
+
+        let mut decoder = SpriteDecoder::new(encoded)?;
+        if decoder.frame_count() == 0 {
+            bail!("Animation {} has no frames", name);
+        }
+        for _ in 0..frame_index % decoder.frame_count() {
+            decoder.next_frame()?;
+        }
+        match decoder.next_frame()? {
+            Some(frame) => Ok(frame.clone()),
+            None => bail!("Animation {} ended early", name),
+        }
+    }
+}
+
+impl Default for Animations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Render a screen as the client would show it. `frame_index` selects the animation frame for
+/// screens that have one.
+pub fn render_screen(screen: &Screen, animations: &Animations, frame_index: usize) -> Result<Frame> {
+    let mut frame = Frame::new(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+
+    match screen {
+        Screen::Message(m) => render::draw_message(&mut frame, m)?,
+        Screen::Timer { current, total } => render::draw_timer(&mut frame, *current, *total)?,
+        Screen::Animation(a) => frame = animations.frame(a, frame_index)?,
+        Screen::Scene { animation, overlay } => {
+            if let Some(a) = animation {
+                frame = animations.frame(a, frame_index)?;
+            }
+            render::draw_overlay(&mut frame, overlay)?;
+        }
+    }
+
+    Ok(frame)
+}
+
+/// Render a frame as text, two pixel rows per line using half block characters.
+pub fn to_terminal(frame: &Frame) -> String {
+    let mut out = String::new();
+
+    for y in (0..frame.height()).step_by(2) {
+        for x in 0..frame.width() {
+            out.push(match (frame.pixel(x, y), frame.pixel(x, y + 1)) {
+                (true, true)   => '█',
+                (true, false)  => '▀',
+                (false, true)  => '▄',
+                (false, false) => ' ',
+            });
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+/// Save a frame as a greyscale PNG, each display pixel scaled up to `scale` x `scale`.
+pub fn save_png(frame: &Frame, path: &Path, scale: usize) -> Result<()> {
+    let width = frame.width() * scale;
+    let height = frame.height() * scale;
+
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let on = frame.pixel(x / scale, y / scale);
+            data.push(if on { 0xff } else { 0x00 });
+        }
+    }
+
+    let writer = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&data)?;
+
+    Ok(())
+}
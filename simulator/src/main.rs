@@ -0,0 +1,53 @@
+// Render a directive the way a worker would display it.
+//
+// cargo run -p simulator -- "MESSAGE Hello"
+// cargo run -p simulator -- --png eyes.png --frame 12 "SCENE CartoonEyes|90/300|Hi"
+
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Result};
+
+use render::Screen;
+use simulator::{render_screen, save_png, to_terminal, Animations};
+
+fn usage() -> ! {
+    eprintln!("usage: simulator [--png <file>] [--scale <n>] [--frame <n>] <directive>");
+    std::process::exit(2);
+}
+
+fn main() -> Result<()> {
+    let mut png: Option<PathBuf> = None;
+    let mut scale = 4;
+    let mut frame_index = 0;
+    let mut directive: Option<String> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--png"   => png = Some(args.next().unwrap_or_else(|| usage()).into()),
+            "--scale" => scale = args.next().unwrap_or_else(|| usage()).parse()?,
+            "--frame" => frame_index = args.next().unwrap_or_else(|| usage()).parse()?,
+            "-h" | "--help" => usage(),
+            _ if directive.is_none() => directive = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let directive = directive.unwrap_or_else(|| usage());
+    if directive == "PING" {
+        bail!("PING doesn't change the screen");
+    }
+
+    let screen = Screen::parse(&directive).ok_or_else(|| anyhow!("Unrecognized directive: {}", directive))?;
+    let frame = render_screen(&screen, &Animations::new(), frame_index)?;
+
+    match png {
+        Some(path) => {
+            save_png(&frame, &path, scale)?;
+            println!("Wrote {}", path.display());
+        }
+        None => print!("{}", to_terminal(&frame)),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+// Screens as the client draws them, against snapshots checked in under tests/snapshots. Run with
+// UPDATE_SNAPSHOTS=1 to write them again after a deliberate change, and review the diff.
+
+use std::path::PathBuf;
+
+use render::Screen;
+use simulator::{render_screen, to_terminal, Animations};
+
+fn assert_snapshot(name: &str, directive: &str, frame_index: usize) {
+    let screen = Screen::parse(directive).unwrap();
+    let frame = render_screen(&screen, &Animations::new(), frame_index).unwrap();
+    let rendered = to_terminal(&frame);
+
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots").join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(&path, &rendered).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("no snapshot {}: {}", path.display(), e));
+    assert!(rendered == expected, "{} doesn't match {}, got:\n{}", directive, path.display(), rendered);
+}
+
+#[test]
+fn message() {
+    assert_snapshot("message", "MESSAGE Hello, world!\nJosé :heart:", 0);
+}
+
+#[test]
+fn timer() {
+    assert_snapshot("timer", "TIMER 90/300", 0);
+}
+
+#[test]
+fn animation() {
+    assert_snapshot("animation", "ANIMATE Heart", 3);
+}
+
+#[test]
+fn scene() {
+    assert_snapshot("scene", "SCENE CartoonEyes|90/300|Hi", 12);
+}
+
+#[test]
+fn scene_without_animation() {
+    assert_snapshot("scene_text_only", "SCENE ||Back at 10:30", 0);
+}
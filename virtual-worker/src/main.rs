@@ -0,0 +1,344 @@
+// A software stand-in for a MicroBroadcast board.
+//
+// Registers with the server like the client does, listens for directives and renders them with
+// the display simulator, to the terminal or as one PNG per worker. Several workers can run from
+// one process, each listening on its own port.
+//
+// Firmware updates are downloaded and checked like a board would, then the worker comes back
+// registering with the new version, or the one it had if the image didn't pass.
+//
+// cargo run -p virtual-worker -- --count 5 --png-dir /tmp/workers
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+
+use registration::Session;
+use render::Screen;
+use simulator::{render_screen, save_png, to_terminal, Animations};
+use telemetry::Telemetry;
+
+// Keeps frames from different workers from interleaving on the terminal
+static TERMINAL: Mutex<()> = Mutex::new(());
+
+#[derive(Clone)]
+enum Output {
+    Terminal,
+    Png(PathBuf),
+    Quiet,
+}
+
+struct VirtualWorker {
+    mac_address: String,
+    listen_port: u16,
+    server_addr: SocketAddr,
+    key: Option<Vec<u8>>,
+    // Release public key images have to be signed with, like boards built with one
+    ota_key: Option<[u8; ota::KEY_LEN]>,
+    firmware: String,
+    output: Output,
+    started: Instant,
+}
+
+impl VirtualWorker {
+
+    fn run(&self) {
+        let animations = Animations::new();
+
+        let mut current_cmd = "".to_string();
+        let mut last_report = Instant::now();
+        let mut firmware = self.firmware.clone();
+
+        loop {
+            let mut session = match TcpStream::connect(self.server_addr) {
+                Ok(mut stream) => match self.register(&mut stream, &firmware) {
+                    Ok(s) => s,
+                    Err(e) => {
+                        println!("[{}] Registration failed {}", self.mac_address, e);
+                        std::thread::sleep(Duration::from_millis(1000));
+                        continue;
+                    }
+                },
+                Err(error) => {
+                    println!("[{}] Searching for MicroBroadcaster at {}: {}", self.mac_address, self.server_addr, error);
+                    std::thread::sleep(Duration::from_millis(1000));
+                    continue;
+                }
+            };
+
+            let listener = match TcpListener::bind(("0.0.0.0", self.listen_port)) {
+                Ok(l) => l,
+                Err(e) => {
+                    println!("[{}] Unable to listen on port {}: {}", self.mac_address, self.listen_port, e);
+                    std::thread::sleep(Duration::from_millis(1000));
+                    continue;
+                }
+            };
+            listener.set_nonblocking(true).unwrap();
+
+            let timeout = Duration::from_secs(5);
+            let mut start_time = Instant::now();
+
+            loop {
+                match listener.accept() {
+                    Ok((mut socket, _addr)) => {
+                        start_time = Instant::now();
+                        socket.set_nonblocking(false).unwrap();
+                        socket.set_read_timeout(Some(Duration::new(1, 0))).unwrap();
+
+                        let mut cmd = "".to_string();
+                        if let Err(e) = socket.read_to_string(&mut cmd) {
+                            println!("[{}] Read Error: {}", self.mac_address, e);
+                            break;
+                        }
+
+                        // Once registered with a key only sealed directives are trusted
+                        if let Some(session) = &mut session {
+                            match session.open(&cmd) {
+                                Some(directive) => cmd = directive,
+                                None => {
+                                    println!("[{}] Dropping directive that isn't sealed for this session", self.mac_address);
+                                    continue;
+                                }
+                            }
+                        }
+
+                        if last_report.elapsed() >= telemetry::INTERVAL {
+                            let report = registration::reply(session.as_mut(), &self.telemetry(&firmware).line());
+                            let _ = socket.write_all(report.as_bytes());
+                            last_report = Instant::now();
+                        }
+
+                        if let Some(update) = ota::Update::parse(&cmd) {
+                            if update.version == firmware {
+                                println!("[{}] Already running {}, update skipped", self.mac_address, update.version);
+                                continue;
+                            }
+                            // Without --ota-key only the session vouches for the image
+                            if self.ota_key.is_none() && session.is_none() {
+                                println!("[{}] Ignoring update to {}, unsealed and no --ota-key to check it", self.mac_address, update.version);
+                                continue;
+                            }
+                            drop(socket);
+                            match self.download(&update) {
+                                Ok(()) => {
+                                    println!("[{}] Updated to {}, restarting", self.mac_address, update.version);
+                                    firmware = update.version;
+                                },
+                                Err(e) => println!("[{}] Update to {} failed: {}", self.mac_address, update.version, e),
+                            }
+                            // Back from a reboot either way, the server learns how it went on registration
+                            current_cmd.clear();
+                            break;
+                        }
+
+                        if cmd != current_cmd && cmd != "PING" {
+                            if let Err(e) = self.show(&cmd, &animations) {
+                                println!("[{}] {}", self.mac_address, e);
+                            }
+                        }
+                        current_cmd = cmd;
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => {
+                        if start_time.elapsed() >= timeout {
+                            println!("[{}] Timeout reached, registering again", self.mac_address);
+                            break;
+                        }
+                        std::thread::sleep(Duration::from_millis(100));
+                    }
+                    Err(e) => {
+                        println!("[{}] Error: {}", self.mac_address, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    fn register(&self, stream: &mut TcpStream, firmware: &str) -> Result<Option<Session>> {
+        stream.write_all(registration::request(&self.mac_address, Some(self.listen_port)).as_bytes())?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+
+        let mut session = None;
+        if let Some(nonce) = registration::parse_challenge(&line) {
+            let key = self.key.as_ref().ok_or_else(|| anyhow!("the server asked for a key, start with --key"))?;
+            stream.write_all(registration::response(key, &nonce, &self.mac_address).as_bytes())?;
+            session = Some(Session::new(key, &nonce, &self.mac_address));
+            line.clear();
+            reader.read_line(&mut line)?;
+        }
+
+        match line.trim() {
+            registration::ACCEPTED => {
+                stream.write_all(registration::reply(session.as_mut(), &self.telemetry(firmware).line()).as_bytes())?;
+                Ok(session)
+            },
+            answer => Err(anyhow!("refused by the server: {:?}", answer)),
+        }
+    }
+
+    // There is no radio or heap to speak of, only what a board would report regardless
+    fn telemetry(&self, firmware: &str) -> Telemetry {
+        Telemetry {
+            uptime: Some(self.started.elapsed().as_secs()),
+            reset_reason: Some("poweron".to_string()),
+            firmware: Some(firmware.to_string()),
+            display: Some("virtual".to_string()),
+            ..Telemetry::default()
+        }
+    }
+
+    // Fetch the image with a plain GET and check it as it arrives, there is no partition to write to
+    fn download(&self, update: &ota::Update) -> Result<()> {
+        let rest = update.url.strip_prefix("http://").ok_or_else(|| anyhow!("only plain HTTP is supported: {}", update.url))?;
+        let (host, path) = rest.split_once('/').ok_or_else(|| anyhow!("Invalid URL {}", update.url))?;
+
+        let mut stream = TcpStream::connect(host)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        stream.write_all(format!("GET /{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host).as_bytes())?;
+
+        let mut reader = BufReader::new(stream);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.split_ascii_whitespace().nth(1) != Some("200") {
+            return Err(anyhow!("server answered {:?}", line.trim_end()));
+        }
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+        }
+
+        let mut verifier = ota::Verifier::new(update);
+        let mut buffer = [0u8; 4096];
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            verifier.update(&buffer[..n])?;
+        }
+        Ok(verifier.finish(self.ota_key.as_ref())?)
+    }
+
+    fn show(&self, cmd: &str, animations: &Animations) -> Result<()> {
+        let screen = Screen::parse(cmd).ok_or_else(|| anyhow!("Unrecognized directive: {}", cmd))?;
+        let frame = render_screen(&screen, animations, 0)?;
+
+        match &self.output {
+            Output::Terminal => {
+                let _terminal = TERMINAL.lock().unwrap();
+                print!("[{}] {}\n{}", self.mac_address, cmd, to_terminal(&frame));
+            }
+            Output::Png(dir) => {
+                let path = dir.join(format!("{}.png", self.mac_address.replace(':', "-")));
+                save_png(&frame, &path, 4)?;
+                println!("[{}] {} -> {}", self.mac_address, cmd, path.display());
+            }
+            Output::Quiet => println!("[{}] {}", self.mac_address, cmd),
+        }
+
+        Ok(())
+    }
+}
+
+// Offset the last bytes of a MAC address, so --count gets a distinct address per worker
+fn nth_mac_address(base: &str, n: u64) -> Result<String> {
+    let parts: Vec<&str> = base.split(':').collect();
+    if parts.len() != 6 {
+        return Err(anyhow!("Invalid MAC address {}", base));
+    }
+
+    let mut value = 0u64;
+    for part in parts {
+        value = (value << 8) | u64::from_str_radix(part, 16)?;
+    }
+    let value = value.wrapping_add(n);
+
+    let bytes = value.to_be_bytes();
+    Ok(bytes[2..].iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(":"))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: virtual-worker [--mac <address>] [--count <n>] [--port <first listen port>]");
+    eprintln!("                      [--server <ip:port>] [--key <hex>] [--png-dir <dir> | --quiet]");
+    eprintln!("                      [--firmware <version>] [--ota-key <release public key hex>]");
+    std::process::exit(2);
+}
+
+fn main() -> Result<()> {
+    let mut mac_address = "02:4D:42:00:00:01".to_string();
+    let mut count = 1;
+    let mut port = config::BROADCAST_PORT + 1;
+    let mut server_addr: Option<SocketAddr> = None;
+    let mut key = None;
+    let mut ota_key = None;
+    let mut firmware = env!("CARGO_PKG_VERSION").to_string();
+    let mut output = Output::Terminal;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--mac"     => mac_address = value(),
+            "--count"   => count = u64::from_str(&value())?,
+            "--port"    => port = u16::from_str(&value())?,
+            "--server"  => server_addr = Some(SocketAddr::from_str(&value())?),
+            "--key"     => key = Some(registration::parse_key(&value()).ok_or_else(|| anyhow!("--key expects at least 16 bytes of hex"))?),
+            "--ota-key" => ota_key = Some(ota::parse_hex(&value()).ok_or_else(|| anyhow!("--ota-key expects {} bytes of hex", ota::KEY_LEN))?),
+            "--firmware" => firmware = value(),
+            "--png-dir" => output = Output::Png(value().into()),
+            "--quiet"   => output = Output::Quiet,
+            _ => usage(),
+        }
+    }
+
+    // Like the boards, look for the server first and fall back to the configured address
+    let server_addr = match server_addr {
+        Some(addr) => addr,
+        None => match discovery::discover(SocketAddr::from((Ipv4Addr::BROADCAST, discovery::DISCOVERY_PORT)), Duration::from_millis(500), 3)? {
+            Some(addr) => {
+                println!("Discovered MicroBroadcaster at {}", addr);
+                addr
+            },
+            None => SocketAddr::V4(SocketAddrV4::new(config::SERVER_IP, config::BROADCAST_PORT)),
+        },
+    };
+
+    if let Output::Png(dir) = &output {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut workers = Vec::new();
+    for n in 0..count {
+        let worker = VirtualWorker {
+            mac_address: nth_mac_address(&mac_address, n)?,
+            listen_port: port + n as u16,
+            server_addr,
+            key: key.clone(),
+            ota_key,
+            firmware: firmware.clone(),
+            output: output.clone(),
+            started: Instant::now(),
+        };
+
+        println!("Starting virtual worker {} on port {}", worker.mac_address, worker.listen_port);
+        workers.push(std::thread::spawn(move || worker.run()));
+    }
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    Ok(())
+}